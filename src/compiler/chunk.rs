@@ -4,6 +4,7 @@ use std::path::Path;
 use std::convert::TryFrom;
 use std::fs;
 use std::slice;
+use num_bigint::BigInt;
 
 use crate::{HissyError, ErrorType};
 use crate::vm::{MAX_REGISTERS, InstrType, InstrType::*, value::{NIL, Value}, gc::GCHeap};
@@ -11,7 +12,7 @@ use crate::serial::*;
 
 
 fn error(s: String) -> HissyError {
-	HissyError(ErrorType::IO, s, 0)
+	HissyError::new(ErrorType::IO, s, 0)
 }
 fn error_str(s: &str) -> HissyError {
 	error(String::from(s))
@@ -26,15 +27,17 @@ enum ConstantType {
 	Int,
 	Real,
 	String,
+	BigInt,
 }
 
 #[derive(PartialEq)]
 pub(crate) enum ChunkConstant {
 	Nil,
 	Bool(bool),
-	Int(i32),
+	Int(i64),
 	Real(f64),
 	String(String),
+	BigInt(BigInt),
 }
 
 impl ChunkConstant {
@@ -42,9 +45,10 @@ impl ChunkConstant {
 		match self {
 			ChunkConstant::Nil => NIL,
 			ChunkConstant::Bool(b) => Value::from(*b),
-			ChunkConstant::Int(i) => Value::from(*i),
+			ChunkConstant::Int(i) => Value::from_i64(*i, heap),
 			ChunkConstant::Real(r) => Value::from(*r),
 			ChunkConstant::String(s) => heap.make_value(s.clone()),
+			ChunkConstant::BigInt(b) => Value::from_bigint(b.clone(), heap),
 		}
 	}
 	
@@ -55,6 +59,7 @@ impl ChunkConstant {
 			ChunkConstant::Int(i) => format!("{}", *i),
 			ChunkConstant::Real(r) => format!("{}", *r),
 			ChunkConstant::String(s) => format!("{:?}", s),
+			ChunkConstant::BigInt(b) => format!("{}", b),
 		}
 	}
 }
@@ -69,6 +74,7 @@ pub(crate) struct ChunkInfo {
 
 pub(crate) struct Chunk {
 	pub nb_registers: u16,
+	pub is_generator: bool,
 	pub constants: Vec<ChunkConstant>,
 	pub upvalues: Vec<u8>,
 	pub code: Vec<u8>,
@@ -78,7 +84,7 @@ pub(crate) struct Chunk {
 
 impl Chunk {
 	pub fn new() -> Chunk {
-		Chunk { nb_registers: 0, constants: vec![], upvalues: vec![], code: vec![], debug_info: ChunkInfo::default() }
+		Chunk { nb_registers: 0, is_generator: false, constants: vec![], upvalues: vec![], code: vec![], debug_info: ChunkInfo::default() }
 	}
 	
 	pub fn from_bytes(it: &mut slice::Iter<u8>, debug_info: bool) -> Result<Chunk, HissyError> {
@@ -88,6 +94,7 @@ impl Chunk {
 		}
 		
 		chunk.nb_registers = read_u16(it)?;
+		chunk.is_generator = read_u8(it)? != 0;
 		
 		let nb_constants = read_u16(it)?;
 		for _ in 0..nb_constants {
@@ -95,9 +102,11 @@ impl Chunk {
 			let value = match t {
 				ConstantType::Nil => ChunkConstant::Nil,
 				ConstantType::Bool => ChunkConstant::Bool(read_u8(it)? != 0),
-				ConstantType::Int => ChunkConstant::Int(read_i32(it)?),
+				ConstantType::Int => ChunkConstant::Int(read_i64(it)?),
 				ConstantType::Real => ChunkConstant::Real(read_f64(it)?),
 				ConstantType::String => ChunkConstant::String(read_str(it)?),
+				ConstantType::BigInt => ChunkConstant::BigInt(read_str(it)?.parse()
+					.map_err(|_| error_str("Invalid big integer constant"))?),
 			};
 			chunk.constants.push(value);
 		}
@@ -129,6 +138,7 @@ impl Chunk {
 		}
 		
 		write_u16(bytes, self.nb_registers);
+		write_u8(bytes, if self.is_generator { 1 } else { 0 });
 		
 		write_into_u16(bytes, self.constants.len(), error_str("Too many constants to serialize"))?;
 		for cst in &self.constants {
@@ -142,7 +152,7 @@ impl Chunk {
 				},
 				ChunkConstant::Int(i) => {
 					write_u8(bytes, ConstantType::Int as u8);
-					write_i32(bytes, *i);
+					write_i64(bytes, *i);
 				},
 				ChunkConstant::Real(r) => {
 					write_u8(bytes, ConstantType::Real as u8);
@@ -152,6 +162,10 @@ impl Chunk {
 					write_u8(bytes, ConstantType::String as u8);
 					write_str(bytes, s)?;
 				},
+				ChunkConstant::BigInt(b) => {
+					write_u8(bytes, ConstantType::BigInt as u8);
+					write_str(bytes, &b.to_string())?;
+				},
 			}
 		}
 		
@@ -190,7 +204,7 @@ impl Chunk {
 		let reg = MAX_REGISTERS as usize + self.constants.len();
 		self.constants.push(val);
 		u8::try_from(reg)
-			.map_err(|_| HissyError(ErrorType::Compilation, String::from("Too many constants required"), 0))
+			.map_err(|_| HissyError::new(ErrorType::Compilation, String::from("Too many constants required"), 0))
 	}
 	
 	fn format_reg(&self, it: &mut slice::Iter<u8>) -> Result<String, HissyError> {
@@ -215,10 +229,11 @@ impl Chunk {
 pub struct Program {
 	pub(crate) debug_info: bool,
 	pub(crate) chunks: Vec<Chunk>,
+	pub(crate) globals: Vec<(String, u8)>, // Top-level bindings of the main chunk, and their registers
 }
 
 const MAGIC_BYTES: &[u8; 4] = b"hsyc";
-const FORMAT_VER: u16 = 4;
+const FORMAT_VER: u16 = 5;
 
 impl Program {
 	/// Reads a `Program` from a bytecode file.
@@ -242,12 +257,17 @@ impl Program {
 		}
 		let debug_info = options == 1;
 		
+		let mut globals = vec![];
+		for _ in 0..read_u16(&mut it)? {
+			globals.push((read_small_str(&mut it)?, read_u8(&mut it)?));
+		}
+		
 		let mut chunks = vec![];
 		while it.len() > 0 {
 			chunks.push(Chunk::from_bytes(&mut it, debug_info)?);
 		}
 		
-		Ok(Program { debug_info, chunks })
+		Ok(Program { debug_info, chunks, globals })
 	}
 	
 	/// Serializes a `Program` object to a bytecode file.
@@ -260,6 +280,12 @@ impl Program {
 		let options = if self.debug_info { 1 } else { 0 };
 		bytes.push(options);
 		
+		write_into_u16(&mut bytes, self.globals.len(), error_str("Too many globals to serialize"))?;
+		for (name, reg) in &self.globals {
+			write_small_str(&mut bytes, name);
+			bytes.push(*reg);
+		}
+		
 		for chunk in &self.chunks {
 			chunk.to_bytes(&mut bytes, self.debug_info)?;
 		}
@@ -282,8 +308,9 @@ impl Program {
 		}
 		
 		for (chunk_id, chunk) in self.chunks.iter().enumerate() {
-			println!("{} ({} registers; {} constants)", self.format_chunk_name(chunk_id)?,
-				chunk.nb_registers, chunk.constants.len());
+			let kind = if chunk.is_generator { "generator; " } else { "" };
+			println!("{} ({}{} registers; {} constants)", self.format_chunk_name(chunk_id)?,
+				kind, chunk.nb_registers, chunk.constants.len());
 			
 			if !chunk.upvalues.is_empty() {
 				print!("(upvalues: ");
@@ -315,12 +342,13 @@ impl Program {
 				print!("{:?}(", instr);
 				match instr {
 					Nop => {},
-					Cpy | Neg | Not => {
+					Cpy | Neg | Not | BitNot | Iter => {
 						print!("{}, {}", chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?);
 					},
-					Add | Sub | Mul | Div | Mod | Pow | Or | And
+					Add | Sub | Mul | Div | Mod | Pow | IntDiv | Or | And
+						| BitAnd | BitOr | BitXor | Shl | Shr
 						| Eq | Neq | Lth | Leq | Gth | Geq
-						| ListGet | ListSet => {
+						| ListGet | ListSet | Range | RangeIncl => {
 						print!("{}, {}, {}", chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?);
 					},
 					Func => {
@@ -329,15 +357,18 @@ impl Program {
 					Call => {
 						print!("{}, {}, {}, {}", chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?, read_u8(&mut it)?, chunk.format_reg(&mut it)?);
 					},
-					Ret | ListNew | CloseUp => {
+					Ret | Yield | ListNew | CloseUp => {
 						print!("{}", chunk.format_reg(&mut it)?);
 					},
 					Jmp => {
 						print!("{}", chunk.format_rel_add(&mut it));
 					},
-					Jit | Jif | Jin => {
+					Jit | Jif => {
 						print!("{}, {}", chunk.format_rel_add(&mut it), chunk.format_reg(&mut it)?);
 					},
+					IterNext => {
+						print!("{}, {}, {}", chunk.format_rel_add(&mut it), chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?);
+					},
 					GetUp | SetUp => {
 						print!("u{}, {}", read_u8(&mut it)?, chunk.format_reg(&mut it)?);
 					},
@@ -350,6 +381,12 @@ impl Program {
 					CallMethod => {
 						print!("e{}, .{}, {}, {}, {}, {}", read_u16(&mut it)?, read_u8(&mut it)?, chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?, read_u8(&mut it)?, chunk.format_reg(&mut it)?);
 					},
+					GetMember => {
+						print!("{}, .{}, {}", chunk.format_reg(&mut it)?, read_u8(&mut it)?, chunk.format_reg(&mut it)?);
+					},
+					Slice => {
+						print!("{}, {}, {}, {}", chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?);
+					},
 					ListExtend => {
 						print!("{}, {}, {}", chunk.format_reg(&mut it)?, chunk.format_reg(&mut it)?, read_u8(&mut it)?);
 					},
@@ -15,13 +15,13 @@ use std::convert::TryFrom;
 use crate::{HissyError, ErrorType};
 use crate::serial::write_u16;
 use crate::parser::{parse, ast, ast::*};
-use crate::vm::{MAX_REGISTERS, InstrType, prelude};
+use crate::vm::{MAX_REGISTERS, InstrType, prelude, Environment};
 use chunk::{Chunk, ChunkConstant};
 
 
 
 fn error(s: String) -> HissyError {
-	HissyError(ErrorType::Compilation, s, 0)
+	HissyError::new(ErrorType::Compilation, s, 0)
 }
 fn error_str(s: &str) -> HissyError {
 	error(String::from(s))
@@ -104,9 +104,13 @@ impl ChunkRegisters {
 		}
 	}
 	
+	pub fn is_temp(&self, i: u8) -> bool {
+		i < MAX_REGISTERS && u16::from(i) >= self.local_cnt
+	}
+	
 	// Marks register as freed if temporary
 	pub fn free_temp_reg(&mut self, i: u8) {
-		if i < MAX_REGISTERS && u16::from(i) >= self.local_cnt {
+		if self.is_temp(i) {
 			self.free_reg(i);
 		}
 	}
@@ -146,15 +150,17 @@ struct ChunkContext {
 	blocks: Vec<BlockContext>,
 	upvalues: Vec<UpvalueBinding>,
 	ret_ty: Type,
+	yield_ty: Option<Type>,
 }
 
 impl ChunkContext {
-	pub fn new(ret_ty: Type) -> ChunkContext {
+	pub fn new(ret_ty: Type, yield_ty: Option<Type>) -> ChunkContext {
 		ChunkContext {
 			regs: ChunkRegisters::new(),
 			blocks: Vec::new(),
 			upvalues: Vec::new(),
 			ret_ty,
+			yield_ty,
 		}
 	}
 	
@@ -220,18 +226,20 @@ impl ChunkContext {
 struct Context {
 	stack: Vec<ChunkContext>,
 	external: Vec<(String, Type)>,
+	object_types: Vec<String>,
 }
 
 impl Context {
-	pub fn new() -> Context {
+	pub fn new(external: Vec<(String, Type)>, object_types: Vec<String>) -> Context {
 		Context {
 			stack: Vec::new(),
-			external: prelude::list(),
+			external,
+			object_types,
 		}
 	}
 	
-	fn enter(&mut self, ret_ty: Type) {
-		self.stack.push(ChunkContext::new(ret_ty));
+	fn enter(&mut self, ret_ty: Type, yield_ty: Option<Type>) {
+		self.stack.push(ChunkContext::new(ret_ty, yield_ty));
 	}
 	
 	fn leave(&mut self) {
@@ -330,7 +338,8 @@ impl DerefMut for ChunkManager {
 }
 
 
-fn resolve_type(ty: &ast::Type) -> Result<Type, HissyError> {
+// object_types are the names of the object types defined by the host
+fn resolve_type(ty: &ast::Type, object_types: &[String]) -> Result<Type, HissyError> {
 	match ty {
 		ast::Type::Named(name) => {
 			match name.deref() {
@@ -340,20 +349,45 @@ fn resolve_type(ty: &ast::Type) -> Result<Type, HissyError> {
 				"Int" => Ok(prim_ty!(Int)),
 				"Real" => Ok(prim_ty!(Real)),
 				"String" => Ok(prim_ty!(String)),
+				"Coroutine" => Ok(Type::Coroutine),
+				"Map" => Ok(Type::Map),
+				_ if object_types.contains(name) => Ok(Type::Object(name.clone())),
 				_ => Err(error(format!("Unknown type name '{}'", name)))
 			}
 		},
+		ast::Type::Parametric(name, params) => {
+			let mut params: Vec<Type> = params.iter().map(|ty| resolve_type(ty, object_types)).collect::<Result<_, _>>()?;
+			match (name.deref(), params.len()) {
+				("List", 1) => Ok(Type::List(Box::new(params.remove(0)))),
+				("Iterator", 1) => Ok(Type::Iterator(Box::new(params.remove(0)))),
+				_ => Err(error(format!("Unknown type name '{}' with {} parameters", name, params.len())))
+			}
+		},
 		ast::Type::Function(args, res) => {
-			let args: Result<Vec<Type>, HissyError> = args.iter().map(resolve_type).collect();
-			Ok(Type::TypedFunction(args?, Box::new(resolve_type(res)?)))
+			let args: Result<Vec<Type>, HissyError> = args.iter().map(|ty| resolve_type(ty, object_types)).collect();
+			Ok(Type::TypedFunction(args?, Box::new(resolve_type(res, object_types)?)))
 		},
 	}
 }
 
-fn resolve_function_type(args: &[(String, ast::Type)], res_ty: &ast::Type) -> Result<Type, HissyError> {
-	let args_ty: Result<Vec<Type>, HissyError> = args.iter().map(|(_,t)| Ok(resolve_type(t)?)).collect();
+// For generator functions, the result type is the type of the iterator returned by the call
+fn resolve_result_type(res_ty: &ast::Type, body: &Block, object_types: &[String]) -> Result<Type, HissyError> {
+	let res_ty = resolve_type(res_ty, object_types)?;
+	if contains_yield(body) {
+		match res_ty {
+			prim_ty!(Nil) => Ok(Type::Iterator(Box::new(Type::Any))),
+			Type::Iterator(_) => Ok(res_ty),
+			_ => Err(error(format!("Generator functions must return an Iterator, not {:?}", res_ty))),
+		}
+	} else {
+		Ok(res_ty)
+	}
+}
+
+fn resolve_function_type(args: &[(String, ast::Type)], res_ty: &ast::Type, body: &Block, object_types: &[String]) -> Result<Type, HissyError> {
+	let args_ty: Result<Vec<Type>, HissyError> = args.iter().map(|(_,t)| resolve_type(t, object_types)).collect();
 	let args_ty = args_ty?;
-	let res_ty = resolve_type(res_ty)?;
+	let res_ty = resolve_result_type(res_ty, body, object_types)?;
 	Ok(Type::TypedFunction(args_ty, Box::new(res_ty)))
 }
 
@@ -375,9 +409,22 @@ fn can_reach_end(block: &Block) -> bool {
 	true
 }
 
+// Does not look inside nested functions, which are compiled into their own chunks
+fn contains_yield(block: &Block) -> bool {
+	block.iter().any(|Positioned(stat, _)| {
+		match stat {
+			Stat::Yield(_) => true,
+			Stat::Cond(branches) => branches.iter().any(|(_, block2)| contains_yield(block2)),
+			Stat::While(_, block2) | Stat::For(_, _, _, block2) => contains_yield(block2),
+			_ => false,
+		}
+	})
+}
+
 
 enum ObjectProp {
 	Method { ns_idx: u16, prop_idx: u8, prop_ty: Type },
+	Member { prop_idx: u8, prop_ty: Type },
 }
 
 
@@ -386,15 +433,27 @@ pub struct Compiler {
 	debug_info: bool,
 	ctx: Context,
 	chunk: ChunkManager,
+	globals: Vec<(String, u8)>,
 }
 
 impl Compiler {
-	/// Creates a new `Compiler` object.
+	/// Creates a new `Compiler` object, for programs which only use the prelude.
 	pub fn new(debug_info: bool) -> Compiler {
 		Compiler {
 			debug_info,
-			ctx: Context::new(),
+			ctx: Context::new(prelude::list(), vec![]),
+			chunk: ChunkManager::new(),
+			globals: vec![],
+		}
+	}
+	
+	/// Creates a new `Compiler` object, for programs which use the externals of `env`.
+	pub fn with_environment(debug_info: bool, env: &Environment) -> Compiler {
+		Compiler {
+			debug_info,
+			ctx: Context::new(env.types(), env.object_types().to_vec()),
 			chunk: ChunkManager::new(),
+			globals: vec![],
 		}
 	}
 	
@@ -418,13 +477,27 @@ impl Compiler {
 			else { return Ok(None); };
 		let prop_idx = u8::try_from(prop_idx)
 			.map_err(|_| error_str("Namespace has too many methods"))?;
-		let prop_ty = props[prop_idx as usize].1.clone();
+		let prop_ty = props[prop_idx as usize].1.subst_params(&[ty.element_type()]);
 		Ok(Some((ns_idx, prop_idx, prop_ty)))
 	}
 	
+	fn find_member(ty: &Type, prop: &str) -> Result<Option<(u8, Type)>, HissyError> {
+		let props = if let Type::Module(props) = ty { props }
+			else { return Ok(None); };
+		let prop_idx = if let Some(prop_idx) = props.iter().position(|(id, _)| id == prop) { prop_idx }
+			else { return Ok(None); };
+		let prop_ty = props[prop_idx].1.clone();
+		let prop_idx = u8::try_from(prop_idx)
+			.map_err(|_| error_str("Module has too many members"))?;
+		Ok(Some((prop_idx, prop_ty)))
+	}
+	
 	fn find_prop(&mut self, val: Expr, prop: &str) -> Result<(Type, Option<(u8, ObjectProp)>), HissyError> {
 		let (val, ty) = self.compile_expr(val, None, None)?;
 		
+		if let Some((prop_idx, prop_ty)) = Compiler::find_member(&ty, prop)? {
+			return Ok((ty, Some((val, ObjectProp::Member { prop_idx, prop_ty }))));
+		}
 		let prop = self.find_method(ty.clone(), prop)?.map(|(ns_idx, prop_idx, prop_ty)| {
 			(val, ObjectProp::Method { ns_idx, prop_idx, prop_ty })
 		});
@@ -444,16 +517,19 @@ impl Compiler {
 		};
 		let n = u8::try_from(args.len()).map_err(|_| error_str("Too many function arguments"))?;
 		let arg_range = self.ctx.regs.new_reg_range(n)?;
+		let mut bindings = vec![]; // Type parameters of generic methods are bound by the arguments
 		for (i, arg) in args.drain(..).enumerate() {
 			let rout = u8::try_from(usize::from(arg_range) + i).unwrap();
 			let (_, t) = self.compile_expr(arg, Some(rout), None)?;
 			if let Some(args_ty) = &args_ty {
-				if !args_ty[i].can_assign(&t) {
-					return Err(error(format!("Expected argument of type {:?}, got {:?}", args_ty[i], t)));
+				args_ty[i].bind_params(&t, &mut bindings);
+				let arg_ty = args_ty[i].subst_params(&bindings).erase_params();
+				if !arg_ty.can_assign(&t) {
+					return Err(error(format!("Expected argument of type {:?}, got {:?}", arg_ty, t)));
 				}
 			}
 		}
-		Ok((arg_range, n, *res_ty))
+		Ok((arg_range, n, res_ty.subst_params(&bindings).erase_params()))
 	}
 	
 	// Compile computation of expr (into dest if given), and returns final register
@@ -469,6 +545,8 @@ impl Compiler {
 				(self.chunk.compile_constant(ChunkConstant::Bool(b))?, prim_ty!(Bool)),
 			Expr::Int(i) =>
 				(self.chunk.compile_constant(ChunkConstant::Int(i))?, prim_ty!(Int)),
+			Expr::BigInt(b) =>
+				(self.chunk.compile_constant(ChunkConstant::BigInt(b))?, prim_ty!(Int)),
 			Expr::Real(r) =>
 				(self.chunk.compile_constant(ChunkConstant::Real(r))?, prim_ty!(Real)),
 			Expr::String(s) => 
@@ -502,6 +580,7 @@ impl Compiler {
 					BinOp::Minus => InstrType::Sub,
 					BinOp::Times => InstrType::Mul,
 					BinOp::Divides => InstrType::Div,
+					BinOp::IntDivides => InstrType::IntDiv,
 					BinOp::Modulo => InstrType::Mod,
 					BinOp::Power => InstrType::Pow,
 					BinOp::LEq => InstrType::Leq,
@@ -512,6 +591,11 @@ impl Compiler {
 					BinOp::NEq => InstrType::Neq,
 					BinOp::And => InstrType::And,
 					BinOp::Or => InstrType::Or,
+					BinOp::BitAnd => InstrType::BitAnd,
+					BinOp::BitOr => InstrType::BitOr,
+					BinOp::BitXor => InstrType::BitXor,
+					BinOp::ShiftLeft => InstrType::Shl,
+					BinOp::ShiftRight => InstrType::Shr,
 				};
 				let ty = match op {
 					  BinOp::Plus | BinOp::Minus | BinOp::Times | BinOp::Divides
@@ -519,11 +603,18 @@ impl Compiler {
 						if !t1.is_numeric() || !t2.is_numeric() {
 							return Err(error(format!("Cannot use numeric operator on {:?} and {:?}", t1, t2)));
 						}
-						if t1 == prim_ty!(Int) && t2 == prim_ty!(Int) && op != BinOp::Power {
+						if t1 == prim_ty!(Int) && t2 == prim_ty!(Int) && op != BinOp::Divides {
 							prim_ty!(Int)
 						} else {
 							prim_ty!(Real)
 						}
+					},
+					  BinOp::IntDivides | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor
+					| BinOp::ShiftLeft | BinOp::ShiftRight => {
+						if t1 != prim_ty!(Int) || t2 != prim_ty!(Int) {
+							return Err(error(format!("Cannot use integer operator on {:?} and {:?}", t1, t2)));
+						}
+						prim_ty!(Int)
 					},
 					BinOp::LEq | BinOp::GEq | BinOp::Less | BinOp::Greater => {
 						if !t1.is_numeric() || !t2.is_numeric() {
@@ -546,11 +637,12 @@ impl Compiler {
 				(self.emit_reg(dest)?, ty)
 			},
 			Expr::UnaOp(op, e) => {
-				let (r, t) = self.compile_expr(*e, dest, None)?;
+				let (r, t) = self.compile_expr(*e, None, None)?;
 				self.ctx.regs.free_temp_reg(r);
 				let instr = match op {
 					UnaOp::Not => InstrType::Not,
 					UnaOp::Minus => InstrType::Neg,
+					UnaOp::BitNot => InstrType::BitNot,
 				};
 				let ty = match op {
 					UnaOp::Not => {
@@ -565,6 +657,12 @@ impl Compiler {
 						}
 						t.clone()
 					},
+					UnaOp::BitNot => {
+						if t != prim_ty!(Int) {
+							return Err(error(format!("Cannot use integer operator on {:?}", t)));
+						}
+						prim_ty!(Int)
+					},
 				};
 				self.chunk.emit_instr(instr);
 				self.chunk.emit_byte(r);
@@ -587,6 +685,23 @@ impl Compiler {
 							needs_copy = false;
 							(self.emit_reg(dest)?, res_ty)
 						},
+						(_ty, Some((val, ObjectProp::Member { prop_idx, prop_ty }))) => {
+							self.ctx.regs.free_temp_reg(val);
+							let func = self.ctx.regs.new_reg()?;
+							self.chunk.emit_instr(InstrType::GetMember);
+							self.chunk.emit_byte(val);
+							self.chunk.emit_byte(prop_idx);
+							self.chunk.emit_byte(func);
+							let (arg_range, n, res_ty) = self.compile_arguments(prop_ty, args)?;
+							self.ctx.regs.free_temp_range(arg_range, n);
+							self.ctx.regs.free_temp_reg(func);
+							self.chunk.emit_instr(InstrType::Call);
+							self.chunk.emit_byte(func);
+							self.chunk.emit_byte(arg_range);
+							self.chunk.emit_byte(n);
+							needs_copy = false;
+							(self.emit_reg(dest)?, res_ty)
+						},
 						(ty, None) => return Err(error(format!("Cannot call undefined property {} of type {:?}", prop, ty)))
 					}
 					
@@ -604,9 +719,10 @@ impl Compiler {
 				}
 			},
 			Expr::Function(args, ret_ty, bl) =>  {
-				let ty = resolve_function_type(&args, &ret_ty)?;
-				let ret_ty = resolve_type(&ret_ty)?;
-				let args: Result<Vec<(String, Type)>, HissyError> = args.iter().map(|(n,t)| Ok((n.clone(), resolve_type(t)?))).collect();
+				let object_types = &self.ctx.object_types;
+				let ty = resolve_function_type(&args, &ret_ty, &bl, object_types)?;
+				let ret_ty = resolve_result_type(&ret_ty, &bl, object_types)?;
+				let args: Result<Vec<(String, Type)>, HissyError> = args.iter().map(|(n,t)| Ok((n.clone(), resolve_type(t, object_types)?))).collect();
 				let args = args?;
 				let new_chunk = self.compile_chunk(name.unwrap_or_else(|| String::from("<func>")), bl, args, ret_ty)?;
 				self.chunk.emit_instr(InstrType::Func);
@@ -657,25 +773,70 @@ impl Compiler {
 				if ti != prim_ty!(Int) {
 					return Err(error(format!("Cannot index list with {:?}", ti)));
 				}
-				self.ctx.regs.free_temp_reg(list);
 				self.ctx.regs.free_temp_reg(index);
+				self.ctx.regs.free_temp_reg(list);
 				self.chunk.emit_instr(InstrType::ListGet);
 				self.chunk.emit_byte(list);
 				self.chunk.emit_byte(index);
 				needs_copy = false;
 				(self.emit_reg(dest)?, tr)
 			},
+			Expr::Slice(val, start, end) => {
+				let (val, ty) = self.compile_expr(*val, None, None)?;
+				match ty {
+					Type::List(_) | prim_ty!(String) => (),
+					_ => return Err(error(format!("Cannot slice object of type {:?}", ty))),
+				}
+				let mut bounds = vec![];
+				for bound in [start, end] {
+					let (bound, tb) = self.compile_expr(bound.map_or(Expr::Nil, |b| *b), None, None)?;
+					if tb != prim_ty!(Int) && tb != prim_ty!(Nil) {
+						return Err(error(format!("Cannot slice with {:?}", tb)));
+					}
+					bounds.push(bound);
+				}
+				self.ctx.regs.free_temp_reg(bounds[1]);
+				self.ctx.regs.free_temp_reg(bounds[0]);
+				self.ctx.regs.free_temp_reg(val);
+				self.chunk.emit_instr(InstrType::Slice);
+				self.chunk.emit_byte(val);
+				self.chunk.emit_byte(bounds[0]);
+				self.chunk.emit_byte(bounds[1]);
+				needs_copy = false;
+				(self.emit_reg(dest)?, ty)
+			},
+			Expr::Range(start, end, inclusive) => {
+				let (start, t1) = self.compile_expr(*start, None, None)?;
+				let (end, t2) = self.compile_expr(*end, None, None)?;
+				if t1 != prim_ty!(Int) || t2 != prim_ty!(Int) {
+					return Err(error(format!("Range bounds must be integers, got {:?} and {:?}", t1, t2)));
+				}
+				self.ctx.regs.free_temp_reg(end);
+				self.ctx.regs.free_temp_reg(start);
+				self.chunk.emit_instr(if inclusive { InstrType::RangeIncl } else { InstrType::Range });
+				self.chunk.emit_byte(start);
+				self.chunk.emit_byte(end);
+				needs_copy = false;
+				(self.emit_reg(dest)?, Type::Iterator(Box::new(prim_ty!(Int))))
+			},
 			Expr::Prop(val, prop) => {
 				let (val, ty) = self.compile_expr(*val, None, None)?;
 				
-				if let Some((ns_idx, prop_idx, prop_ty)) = self.find_method(ty.clone(), &prop)? {
+				if let Some((prop_idx, prop_ty)) = Compiler::find_member(&ty, &prop)? {
+					self.ctx.regs.free_temp_reg(val);
+					self.chunk.emit_instr(InstrType::GetMember);
+					self.chunk.emit_byte(val);
+					self.chunk.emit_byte(prop_idx);
+					needs_copy = false;
+					(self.emit_reg(dest)?, prop_ty.erase_params())
+				} else if let Some((ns_idx, prop_idx, prop_ty)) = self.find_method(ty.clone(), &prop)? {
 					self.ctx.regs.free_temp_reg(val);
 					self.chunk.emit_instr(InstrType::MakeMethod);
 					write_u16(&mut self.chunk.code, ns_idx as u16);
 					self.chunk.emit_byte(prop_idx);
 					self.chunk.emit_byte(val);
 					needs_copy = false;
-					(self.emit_reg(dest)?, prop_ty)
+					(self.emit_reg(dest)?, prop_ty.erase_params())
 				} else {
 					return Err(error(format!("Type {:?} does not have a property {}", ty, prop)));
 				}
@@ -720,14 +881,15 @@ impl Compiler {
 						self.ctx.regs.free_temp_reg(reg);
 					},
 					Stat::Let(id, ty, e) => {
-						let ty = ty.map(|ty| resolve_type(&ty)).transpose()?;
+						let ty = ty.map(|ty| resolve_type(&ty, &self.ctx.object_types)).transpose()?;
 						if let Some(local) = self.ctx.find_block_local(&id) { // if binding already exists
 							self.ctx.regs.free_reg(local.reg);
 						}
 						let reg = self.ctx.regs.new_reg()?;
 						let forwarded = {
-							if let Expr::Function(args, res_ty, _) = &e {
-								self.ctx.make_local(id.clone(), reg, resolve_function_type(args, res_ty)?);
+							if let Expr::Function(args, res_ty, bl) = &e {
+								let ty = resolve_function_type(args, res_ty, bl, &self.ctx.object_types)?;
+								self.ctx.make_local(id.clone(), reg, ty);
 								true
 							} else {
 								false
@@ -783,9 +945,9 @@ impl Compiler {
 						if !te.can_assign(&te2) {
 							return Err(error(format!("Cannot assign type {:?} into list of {:?}", te2, te)));
 						}
-						self.ctx.regs.free_temp_reg(lst);
-						self.ctx.regs.free_temp_reg(idx);
 						self.ctx.regs.free_temp_reg(e);
+						self.ctx.regs.free_temp_reg(idx);
+						self.ctx.regs.free_temp_reg(lst);
 						self.chunk.emit_instr(InstrType::ListSet);
 						self.chunk.emit_byte(lst);
 						self.chunk.emit_byte(idx);
@@ -855,44 +1017,39 @@ impl Compiler {
 						fill_in_jump_from(&mut self.chunk, placeholder)?;
 					},
 					Stat::For(id, el_ty, e, bl) => {
-						let el_ty = el_ty.map(|ty| resolve_type(&ty)).transpose()?;
+						let el_ty = el_ty.map(|ty| resolve_type(&ty, &self.ctx.object_types)).transpose()?;
 						
-						let res = match self.find_prop(e, "next")? {
-							(it_ty, Some((it_reg, ObjectProp::Method { ns_idx, prop_idx, prop_ty: _prop_ty }))) => {
-								if let Type::Iterator(el_ty2) = it_ty {
-									let el_ty = if let Some(el_ty) = el_ty {
-										if !el_ty.can_assign(&el_ty2) {
-											return Err(error(format!("Cannot define variable of type {:?} from iterator on type {:?}", el_ty, el_ty2)));
-										}
-										el_ty
-									} else {
-										*el_ty2
-									};
-									
-									// Hacky way of making the iterator a "persistent temporary"
-									self.ctx.regs.make_local(it_reg);
-									let var_reg = self.ctx.regs.new_reg()?;
-									
-									let begin = self.chunk.code.len();
-									self.chunk.emit_instr(InstrType::CallMethod);
-									write_u16(&mut self.chunk.code, ns_idx as u16);
-									self.chunk.emit_byte(prop_idx);
-									self.chunk.emit_byte(it_reg);
-									self.chunk.emit_byte(it_reg + 1);
-									self.chunk.emit_byte(0);
-									self.chunk.emit_byte(var_reg);
-									Ok((it_reg, var_reg, el_ty, begin))
-								} else {
-									Err(it_ty)
-								}
-							},
-							(it_ty, None) => Err(it_ty),
+						let (val_reg, val_ty) = self.compile_expr(e, None, None)?;
+						let el_ty2 = match val_ty {
+							Type::List(el_ty2) | Type::Iterator(el_ty2) => *el_ty2,
+							Type::Coroutine | Type::Any => Type::Any,
+							ty => return Err(error(format!("{:?} is not an iterable type", ty))),
+						};
+						let el_ty = if let Some(el_ty) = el_ty {
+							if !el_ty.can_assign(&el_ty2) {
+								return Err(error(format!("Cannot define variable of type {:?} from iterator on type {:?}", el_ty, el_ty2)));
+							}
+							el_ty
+						} else {
+							el_ty2
 						};
-						let (it_reg, var_reg, el_ty, begin) = res.map_err(|ty| error(format!("{:?} is not an iterable type", ty)))?;
 						
-						self.chunk.emit_instr(InstrType::Jin);
+						// Get an iterator in a fresh register, since the value may be stored in a local or upvalue
+						self.ctx.regs.free_temp_reg(val_reg);
+						let it_reg = self.ctx.regs.new_reg()?;
+						self.chunk.emit_instr(InstrType::Iter);
+						self.chunk.emit_byte(val_reg);
+						self.chunk.emit_byte(it_reg);
+						
+						// Hacky way of making the iterator a "persistent temporary"
+						self.ctx.regs.make_local(it_reg);
+						let var_reg = self.ctx.regs.new_reg()?;
+						
+						let begin = self.chunk.code.len();
+						self.chunk.emit_instr(InstrType::IterNext);
 						let placeholder = self.chunk.code.len();
 						self.chunk.emit_byte(0); // Placeholder
+						self.chunk.emit_byte(it_reg);
 						self.chunk.emit_byte(var_reg);
 						
 						self.compile_block(vec![(id, var_reg, el_ty)], bl)?;
@@ -913,6 +1070,17 @@ impl Compiler {
 						self.chunk.emit_instr(InstrType::Ret);
 						self.chunk.emit_byte(reg);
 					},
+					Stat::Yield(e) => {
+						let yield_ty = self.ctx.yield_ty.clone()
+							.ok_or_else(|| error_str("Cannot yield outside of a generator function"))?;
+						let (reg, ty) = self.compile_expr(e, None, None)?;
+						if !yield_ty.can_assign(&ty) {
+							return Err(error(format!("Trying to yield {:?}, expected {:?}", ty, yield_ty)));
+						}
+						self.ctx.regs.free_temp_reg(reg);
+						self.chunk.emit_instr(InstrType::Yield);
+						self.chunk.emit_byte(reg);
+					},
 					#[allow(unreachable_patterns)]
 					_ => return Err(error(format!("Unimplemented statement type: {:?}", stat)))
 				}
@@ -920,12 +1088,20 @@ impl Compiler {
 			};
 			
 			let mut res = compile_stat();
-			if let Err(HissyError(ErrorType::Compilation, err, 0)) = res {
-				res = Err(HissyError(ErrorType::Compilation, err, line));
+			if let Err(HissyError(ErrorType::Compilation, err, 0, _)) = res {
+				res = Err(HissyError::new(ErrorType::Compilation, err, line));
 			}
 			res?;
 		}
 		
+		// Top-level bindings of the main chunk stay open until it returns, so the host can look them up afterwards
+		if self.ctx.stack.len() == 1 && self.ctx.blocks.len() == 1 {
+			self.globals = self.ctx.blocks[0].iter().map(|(id, local)| (id.clone(), local.reg)).collect();
+			self.globals.sort();
+			for local in self.ctx.blocks[0].values_mut() {
+				local.closed_over = false;
+			}
+		}
 		self.ctx.leave_block(&mut self.chunk);
 		
 		assert!(used_before == self.ctx.regs.used, "Leaked registers: {} -> {}", used_before, self.ctx.regs.used);
@@ -937,7 +1113,18 @@ impl Compiler {
 
 	fn compile_chunk(&mut self, name: String, ast: Block, args: Vec<(String, Type)>, ret_ty: Type) -> Result<u8, HissyError> {
 		let chunk_id = self.chunk.enter();
-		self.ctx.enter(ret_ty);
+		
+		// Generators can only return nil, which ends the iteration
+		let yield_ty = match &ret_ty {
+			Type::Iterator(el_ty) if contains_yield(&ast) => Some(*el_ty.clone()),
+			_ => None,
+		};
+		self.chunk.is_generator = yield_ty.is_some();
+		if yield_ty.is_some() {
+			self.ctx.enter(prim_ty!(Nil), yield_ty);
+		} else {
+			self.ctx.enter(ret_ty, None);
+		}
 		
 		if self.debug_info {
 			self.chunk.debug_info.name = name;
@@ -951,7 +1138,7 @@ impl Compiler {
 		let implicit_return = can_reach_end(&ast);
 		let last_line = self.compile_block(args, ast)?;
 		if implicit_return && !self.ctx.ret_ty.can_assign(&prim_ty!(Nil)) {
-			return Err(HissyError(ErrorType::Compilation,
+			return Err(HissyError::new(ErrorType::Compilation,
 				format!("Implicit nil return at end of function, but expected {:?}", self.ctx.ret_ty),
 				last_line));
 		}
@@ -972,8 +1159,9 @@ impl Compiler {
 	pub fn compile_program(mut self, input: &str) -> Result<Program, HissyError> {
 		let ast = parse(input)?;
 		
-		self.compile_chunk(String::from("<main>"), ast, Vec::new(), prim_ty!(Nil))?;
+		// The main chunk may return any value to the host
+		self.compile_chunk(String::from("<main>"), ast, Vec::new(), Type::Any)?;
 		
-		Ok(Program { debug_info: self.debug_info, chunks: self.chunk.finish() })
+		Ok(Program { debug_info: self.debug_info, chunks: self.chunk.finish(), globals: self.globals })
 	}
 }
@@ -16,12 +16,22 @@ pub enum Type {
 	
 	List(Box<Type>),
 	Iterator(Box<Type>),
+	Coroutine,
+	/// A map from strings to values of any type, like a parsed JSON object.
+	Map,
+	/// A type of object defined by the host, whose methods are in the namespace of the same name.
+	Object(String),
 	TypedFunction(Vec<Type>, Box<Type>),
 	UntypedFunction(Box<Type>),
 	
 	Namespace(Vec<(String, Type)>),
+	/// A namespace whose members are accessed directly as properties, like `math.pi`.
+	Module(Vec<(String, Type)>),
 	
 	Any,
+	/// A type parameter in the signature of a generic method, bound at each call.
+	/// For methods, the parameter 0 is the element type of the object the method is called on.
+	Param(u8),
 }
 
 #[macro_export]
@@ -46,8 +56,13 @@ impl fmt::Debug for Type {
 			},
 			Type::UntypedFunction(res_ty) => write!(f, "(...) -> {:?}", res_ty),
 			Type::Iterator(ty) => write!(f, "Iterator<{:?}>", ty),
+			Type::Coroutine => write!(f, "Coroutine"),
+			Type::Map => write!(f, "Map"),
+			Type::Object(name) => write!(f, "{}", name),
 			Type::Namespace(_) => write!(f, "Namespace"),
+			Type::Module(_) => write!(f, "Module"),
 			Type::Any => write!(f, "Any"),
+			Type::Param(i) => write!(f, "T{}", i),
 		}
 	}
 }
@@ -100,15 +115,76 @@ impl Type {
 					false
 				}
 			},
-			Type::Namespace(_) => false,
-			Type::Any => true,
+			Type::Coroutine => *other == Type::Coroutine,
+			Type::Map => *other == Type::Map,
+			Type::Object(_) => self == other,
+			Type::Namespace(_) | Type::Module(_) => false,
+			Type::Any | Type::Param(_) => true,
 		}
 	}
 	
+	pub fn element_type(&self) -> Option<Type> {
+		match self {
+			Type::List(ty) | Type::Iterator(ty) => Some(*ty.clone()),
+			_ => None,
+		}
+	}
+	
+	/// Binds the type parameters of a generic signature, by matching it against an actual type.
+	/// Parameters which are already bound are left unchanged.
+	pub fn bind_params(&self, actual: &Type, bindings: &mut Vec<Option<Type>>) {
+		match (self, actual) {
+			(Type::Param(i), _) => {
+				let i = usize::from(*i);
+				if bindings.len() <= i {
+					bindings.resize(i + 1, None);
+				}
+				if bindings[i].is_none() {
+					bindings[i] = Some(actual.clone());
+				}
+			},
+			(Type::List(t1), Type::List(t2)) | (Type::Iterator(t1), Type::Iterator(t2)) => t1.bind_params(t2, bindings),
+			(Type::TypedFunction(args_ty1, res_ty1), Type::TypedFunction(args_ty2, res_ty2)) => {
+				for (t1, t2) in args_ty1.iter().zip(args_ty2) {
+					t1.bind_params(t2, bindings);
+				}
+				res_ty1.bind_params(res_ty2, bindings);
+			},
+			(Type::UntypedFunction(res_ty1), Type::TypedFunction(_, res_ty2))
+				| (Type::UntypedFunction(res_ty1), Type::UntypedFunction(res_ty2)) => res_ty1.bind_params(res_ty2, bindings),
+			_ => {},
+		}
+	}
+	
+	fn map_params(&self, f: &dyn Fn(u8) -> Type) -> Type {
+		match self {
+			Type::Param(i) => f(*i),
+			Type::List(ty) => Type::List(Box::new(ty.map_params(f))),
+			Type::Iterator(ty) => Type::Iterator(Box::new(ty.map_params(f))),
+			Type::TypedFunction(args_ty, res_ty) =>
+				Type::TypedFunction(args_ty.iter().map(|ty| ty.map_params(f)).collect(), Box::new(res_ty.map_params(f))),
+			Type::UntypedFunction(res_ty) => Type::UntypedFunction(Box::new(res_ty.map_params(f))),
+			_ => self.clone(),
+		}
+	}
+	
+	/// Replaces the bound type parameters in a generic signature.
+	pub fn subst_params(&self, bindings: &[Option<Type>]) -> Type {
+		self.map_params(&|i| bindings.get(usize::from(i)).cloned().flatten().unwrap_or(Type::Param(i)))
+	}
+	
+	/// Replaces the remaining type parameters in a generic signature with `Any`.
+	pub fn erase_params(&self) -> Type {
+		self.map_params(&|_| Type::Any)
+	}
+	
 	pub fn get_method_namespace(&self) -> Option<String> {
 		match self {
 			Type::List(_) => Some(String::from("List")),
 			Type::Iterator(_) => Some(String::from("Iterator")),
+			Type::Coroutine => Some(String::from("Coroutine")),
+			Type::Map => Some(String::from("Map")),
+			Type::Object(name) => Some(name.clone()),
 			_ => None,
 		}
 	}
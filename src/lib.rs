@@ -7,6 +7,8 @@ extern crate num_enum;
 extern crate unicode_xid;
 extern crate peg;
 extern crate smallstr;
+extern crate num_bigint;
+extern crate num_traits;
 
 mod serial;
 
@@ -26,20 +28,79 @@ pub enum ErrorType {
 	Compilation,
 	Execution,
 	IO,
+	/// The script used up its instruction or time budget where it could not be paused.
+	OutOfFuel,
 }
 
+/// A function call in the stack trace of an execution error.
+#[derive(Debug, Clone)]
+pub struct Frame {
+	/// The index of the function's chunk in the program.
+	pub chunk: usize,
+	/// The name of the function, if the program has debug info.
+	pub name: Option<String>,
+	/// The line being executed in the function, or 0 without debug info.
+	pub line: u16,
+}
+
+impl fmt::Display for Frame {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.name {
+			Some(name) => write!(f, "in {}", name)?,
+			None => write!(f, "in chunk #{}", self.chunk)?,
+		}
+		if self.line != 0 {
+			write!(f, " at line {}", self.line)?;
+		}
+		Ok(())
+	}
+}
+
+/// An error, with its type, message, line (or 0 if unknown),
+/// and for execution errors, the stack of calls where it occurred, innermost first.
+/// 
+/// The stack trace is private: errors are created with [`HissyError::new`], and the trace is read with [`HissyError::trace`].
 #[derive(Debug)]
-pub struct HissyError(pub ErrorType, pub String, pub u16);
+pub struct HissyError(pub ErrorType, pub String, pub u16, Vec<Frame>);
+
+impl HissyError {
+	/// Creates an error without a stack trace.
+	pub fn new(ty: ErrorType, msg: String, line: u16) -> HissyError {
+		HissyError(ty, msg, line, vec![])
+	}
+	
+	/// Replaces the stack trace of the error.
+	pub fn with_trace(self, trace: Vec<Frame>) -> HissyError {
+		HissyError(self.0, self.1, self.2, trace)
+	}
+	
+	/// The calls in which the error occurred, innermost first, or nothing if it did not occur during execution.
+	pub fn trace(&self) -> &[Frame] {
+		&self.3
+	}
+}
 
 const RED: &str = "\u{001b}[31;1m";
 const RESET: &str = "\u{001b}[0m";
 
 impl fmt::Display for HissyError {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let HissyError(ty, s, line, trace) = self;
 		write!(f, "{}", RED)?;
-		let HissyError(ty, s, line) = self;
 		let line_str = if *line != 0 { format!(" at line {}", line) } else { String::new() };
-		write!(f, "{:?} error{}:{} {}", ty, line_str, RESET, s)
+		write!(f, "{:?} error{}:{} {}", ty, line_str, RESET, s)?;
+		// Collapse runaway recursion
+		let mut i = 0;
+		while i < trace.len() {
+			let frame = &trace[i];
+			let repeats = trace[i..].iter().take_while(|frame2| frame2.chunk == frame.chunk && frame2.line == frame.line).count();
+			write!(f, "\n  {}", frame)?;
+			if repeats > 1 {
+				write!(f, "\n  ... repeated {} more times", repeats - 1)?;
+			}
+			i += repeats;
+		}
+		Ok(())
 	}
 }
 
@@ -9,11 +9,11 @@ use hissy_lib::{HissyError, ErrorType};
 use hissy_lib::parser;
 use hissy_lib::parser::{lexer::{Tokens, read_tokens}, ast::ProgramAST};
 use hissy_lib::compiler::{Program, Compiler};
-use hissy_lib::vm::{gc::GCHeap, run_program};
+use hissy_lib::vm::{gc::GCHeap, VM, Outcome};
 
 
 fn error(s: String) -> HissyError {
-	HissyError(ErrorType::IO, s, 0)
+	HissyError::new(ErrorType::IO, s, 0)
 }
 fn error_str(s: &str) -> HissyError {
 	error(String::from(s))
@@ -23,24 +23,33 @@ const RED: &str = "\u{001b}[31;1m";
 const GREEN: &str = "\u{001b}[32;1m";
 const RESET: &str = "\u{001b}[0m";
 
-fn display_result<T: Display>(r: Result<T, HissyError>) {
+// Prints the error, and returns the process exit code
+fn report_error(e: HissyError) -> i32 {
+	eprintln!("{}", e);
+	1
+}
+
+fn display_result<T: Display>(r: Result<T, HissyError>) -> i32 {
 	match r {
-		Ok(r) => println!("{}Success:{} {}", GREEN, RESET, r),
-		Err(e) => eprintln!("{}", e),
+		Ok(r) => { println!("{}Success:{} {}", GREEN, RESET, r); 0 },
+		Err(e) => report_error(e),
 	}
 }
 
-fn debug_result<T: Debug>(r: Result<T, HissyError>) {
+fn debug_result<T: Debug>(r: Result<T, HissyError>) -> i32 {
 	match r {
-		Ok(r) => println!("{}Success:{} {:#?}", GREEN, RESET, r),
-		Err(e) => eprintln!("{}", e),
+		Ok(r) => { println!("{}Success:{} {:#?}", GREEN, RESET, r); 0 },
+		Err(e) => report_error(e),
 	}
 }
 
-fn display_error(r: Result<(), HissyError>) {
-	if let Err(e) = r {
-		eprintln!("{}", e);
-	}
+fn display_error(r: Result<(), HissyError>) -> i32 {
+	r.map_or_else(report_error, |_| 0)
+}
+
+// Prints the error if there is one, or returns the exit code of the script
+fn display_status(r: Result<i32, HissyError>) -> i32 {
+	r.unwrap_or_else(report_error)
 }
 
 
@@ -70,22 +79,36 @@ fn list(file: &str) -> Result<(), HissyError> {
 	program.disassemble()
 }
 
-fn interpret(file: &str) -> Result<(), HissyError> {
+// Runs the program, and returns the status code it exited with, or 0 if it finished
+fn execute(program: &Program, seed: Option<&String>, allow_fs: bool, args: &[String]) -> Result<i32, HissyError> {
+	let seed = seed.map(|s| s.parse::<i64>().map_err(|_| error(format!("Invalid seed '{}'", s)))).transpose()?;
+	
+	let mut heap = GCHeap::new();
+	let mut vm = VM::new(&mut heap, program);
+	if let Some(seed) = seed {
+		vm.seed_random(seed as u64);
+	}
+	vm.allow_fs(allow_fs);
+	vm.set_args(&mut heap, args);
+	let code = match vm.start_main(&mut heap)? {
+		Outcome::Exited(code) => code,
+		_ => 0, // Without a budget, the run cannot be paused
+	};
+	drop(vm);
+	heap.collect();
+	Ok(code)
+}
+
+fn interpret(file: &str, seed: Option<&String>, allow_fs: bool, args: &[String]) -> Result<i32, HissyError> {
 	let code = read_to_string(file).map_err(|_| error_str("Unable to open file"))?;
 	let compiler = Compiler::new(true); // Always output debug info when interpreting
 	let program = compiler.compile_program(&code)?;
-	
-	let mut heap = GCHeap::new();
-	run_program(&mut heap, &program)?;
-	Ok(())
+	execute(&program, seed, allow_fs, args)
 }
 
-fn run(file: &str) -> Result<(), HissyError> {
+fn run(file: &str, seed: Option<&String>, allow_fs: bool, args: &[String]) -> Result<i32, HissyError> {
 	let program = Program::from_file(file)?;
-	
-	let mut heap = GCHeap::new();
-	run_program(&mut heap, &program)?;
-	Ok(())
+	execute(&program, seed, allow_fs, args)
 }
 
 
@@ -94,17 +117,20 @@ Usage:
   hissy lex|parse <src>
   hissy compile [--strip] [-o <bytecode>] <src>
   hissy list <bytecode>
-  hissy run <bytecode>
-  hissy interpret <src>
+  hissy run [--seed <n>] [--allow-fs] <bytecode> [-- <args>...]
+  hissy interpret [--seed <n>] [--allow-fs] <src> [-- <args>...]
   hissy --help|--version
 
 Arguments:
   <src>        Path to a Hissy source file (usually .hsy)
   <bytecode>   Path to a Hissy bytecode file (usually .hsyc)
+  <args>       Arguments passed to the script, in its `args` list
 
 Options:
   --strip      Strip debug symbols from output
   -o           Specifies the path of the resulting bytecode
+  --seed       Seeds the random number generator, to make runs reproducible
+  --allow-fs   Gives the script access to the file system
   --help       Print this help message
   --version    Print the version
 ";
@@ -112,31 +138,33 @@ Options:
 struct CommandSpec {
 	name: &'static str,
 	takes_file: bool,
+	takes_script_args: bool,
 	parameters: &'static [&'static str],
 	options: &'static [&'static str]
 }
 impl CommandSpec {
-	const fn new(name: &'static str, takes_file: bool, parameters: &'static [&'static str], options: &'static [&'static str]) -> CommandSpec {
-		CommandSpec { name, takes_file, parameters, options }
+	const fn new(name: &'static str, takes_file: bool, takes_script_args: bool, parameters: &'static [&'static str], options: &'static [&'static str]) -> CommandSpec {
+		CommandSpec { name, takes_file, takes_script_args, parameters, options }
 	}
 }
 
 static COMMANDS: &[CommandSpec] = &[
-	CommandSpec::new("lex", true, &[], &[]),
-	CommandSpec::new("parse", true, &[], &[]),
-	CommandSpec::new("compile", true, &["-o"], &["--strip"]),
-	CommandSpec::new("list", true, &[], &[]),
-	CommandSpec::new("run", true, &[], &[]),
-	CommandSpec::new("interpret", true, &[], &[]),
-	CommandSpec::new("--version", false, &[], &[]),
-	CommandSpec::new("--help", false, &[], &[]),
+	CommandSpec::new("lex", true, false, &[], &[]),
+	CommandSpec::new("parse", true, false, &[], &[]),
+	CommandSpec::new("compile", true, false, &["-o"], &["--strip"]),
+	CommandSpec::new("list", true, false, &[], &[]),
+	CommandSpec::new("run", true, true, &["--seed"], &["--allow-fs"]),
+	CommandSpec::new("interpret", true, true, &["--seed"], &["--allow-fs"]),
+	CommandSpec::new("--version", false, false, &[], &[]),
+	CommandSpec::new("--help", false, false, &[], &[]),
 ];
 
 struct Command {
 	name: &'static str,
 	file: Option<String>,
 	parameters: HashMap<&'static str, String>,
-	options: HashSet<&'static str>
+	options: HashSet<&'static str>,
+	script_args: Vec<String>,
 }
 
 
@@ -152,11 +180,18 @@ fn parse_args(mut args: env::Args) -> Result<Command, String> {
 		file: None,
 		parameters: HashMap::new(),
 		options: HashSet::new(),
+		script_args: vec![],
 	};
 	
 	let mut positional = vec![];
 	while let Some(part) = args.next() {
-		if part.starts_with('-') {
+		if part == "--" {
+			if !cmd_spec.takes_script_args {
+				return Err(format!("Command '{}' does not take script arguments", cmd.name));
+			}
+			cmd.script_args = args.collect();
+			break;
+		} else if part.starts_with('-') {
 			if let Some(opt_spec) = cmd_spec.options.iter().find(|opt| *opt == &part) {
 				cmd.options.insert(opt_spec);
 			} else if let Some(param_spec) = cmd_spec.parameters.iter().find(|opt| *opt == &part) {
@@ -183,22 +218,47 @@ fn parse_args(mut args: env::Args) -> Result<Command, String> {
 
 fn main() {
 	let args = env::args();
-	match parse_args(args) {
+	let code = match parse_args(args) {
 		Ok(cmd) => {
 			match cmd.name {
 				"lex" => display_result(lex(&cmd.file.unwrap())),
 				"parse" => debug_result(parse(&cmd.file.unwrap())),
 				"compile" => display_result(compile(&cmd.file.unwrap(), cmd.parameters.get("-o").cloned(), !cmd.options.contains("--strip"))),
 				"list" => display_error(list(&cmd.file.unwrap())),
-				"interpret" => display_error(interpret(&cmd.file.unwrap())),
-				"run" => display_error(run(&cmd.file.unwrap())),
-				"--version" => println!("Hissy v{}", env!("CARGO_PKG_VERSION")),
-				"--help" => println!("{}", USAGE),
+				"interpret" => display_status(interpret(&cmd.file.unwrap(), cmd.parameters.get("--seed"), cmd.options.contains("--allow-fs"), &cmd.script_args)),
+				"run" => display_status(run(&cmd.file.unwrap(), cmd.parameters.get("--seed"), cmd.options.contains("--allow-fs"), &cmd.script_args)),
+				"--version" => { println!("Hissy v{}", env!("CARGO_PKG_VERSION")); 0 },
+				"--help" => { println!("{}", USAGE); 0 },
 				_ => panic!("Unimplemented command"),
 			}
 		},
 		Err(err) => {
 			eprintln!("{}{}{}\n{}", RED, err, RESET, USAGE);
+			2
 		}
+	};
+	std::process::exit(code);
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn exit_code(code: &str) -> i32 {
+		let program = Compiler::new(true).compile_program(code).unwrap();
+		display_status(execute(&program, None, false, &[]))
+	}
+	
+	#[test]
+	fn test_exit_codes() {
+		assert_eq!(exit_code("let x = 1\n"), 0);
+		assert_eq!(exit_code("exit(3)\n"), 3);
+		assert_eq!(exit_code("exit(0)\nlet l = [1]\nl[5]\n"), 0);
+		assert_eq!(exit_code("let l = [1]\nl[5]\n"), 1);
+		assert_eq!(exit_code("exit(2 ^ 40)\n"), 1);
+		
+		let program = Compiler::new(true).compile_program("").unwrap();
+		assert_eq!(display_status(execute(&program, Some(&String::from("x")), false, &[])), 1); // Invalid seed
 	}
 }
@@ -1,16 +1,19 @@
 
 use std::fmt;
 use std::ops::Deref;
+use num_bigint::BigInt;
 
 /// A binary operator.
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum BinOp {
 	Plus, Minus,
-	Times, Divides, Modulo,
+	Times, Divides, IntDivides, Modulo,
 	Power,
 	LEq, GEq, Less, Greater,
 	Equal, NEq,
 	And, Or,
+	BitAnd, BitOr, BitXor,
+	ShiftLeft, ShiftRight,
 }
 
 /// A unary operator.
@@ -18,6 +21,7 @@ pub enum BinOp {
 pub enum UnaOp {
 	Not,
 	Minus,
+	BitNot,
 }
 
 /// An expression (literals and operations).
@@ -25,7 +29,9 @@ pub enum UnaOp {
 pub enum Expr {
 	Nil,
 	Bool(bool),
-	Int(i32),
+	Int(i64),
+	/// An integer literal which does not fit in an `Int`.
+	BigInt(BigInt),
 	Real(f64),
 	String(String),
 	Id(String),
@@ -34,6 +40,9 @@ pub enum Expr {
 	BinOp(BinOp, Box<Expr>, Box<Expr>),
 	UnaOp(UnaOp, Box<Expr>),
 	Index(Box<Expr>, Box<Expr>),
+	Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+	/// A range of integers; the boolean is true if the end is included.
+	Range(Box<Expr>, Box<Expr>, bool),
 	Call(Box<Expr>, Vec<Expr>),
 	Prop(Box<Expr>, String),
 	Function(Vec<(String, Type)>, Type, Block),
@@ -53,6 +62,7 @@ pub type Branch = (Cond, Block);
 #[derive(Debug, PartialEq, Clone)]
 pub enum Type {
 	Named(String),
+	Parametric(String, Vec<Type>),
 	Function(Vec<Type>, Box<Type>),
 }
 
@@ -73,6 +83,7 @@ pub enum Stat {
 	While(Expr, Block),
 	For(String, Option<Type>, Expr, Block),
 	Return(Expr),
+	Yield(Expr),
 }
 
 /// A token with an associated positioned line number
@@ -28,6 +28,7 @@ peg::parser! {
 				match t {
 					Token::Id(s) => Ok(Expr::Id(s.clone())),
 					Token::Int(i) => Ok(Expr::Int(*i)),
+					Token::BigInt(b) => Ok(Expr::BigInt(b.clone())),
 					Token::Real(r) => Ok(Expr::Real(*r)),
 					Token::String(s) => Ok(Expr::String(s.clone())),
 					_ => Err("literal"),
@@ -53,7 +54,15 @@ peg::parser! {
 		rule primary_expression(pos: &[LineCol]) -> Expr
 			= literal() / list(pos) / parenthesized(pos) / function(pos)
 		
+		// ">>" is lexed as two ">" so that it can also close nested type parameters
+		rule shift_right(pos: &[LineCol]) = p:position!() sym(">") sym(">") {?
+			if pos[p].offset + 1 == pos[p + 1].offset { Ok(()) } else { Err(">>") }
+		}
+		
 		pub rule expression(pos: &[LineCol]) -> Expr = precedence!{
+			x:(@) sym("..") y:@ { Expr::Range(Box::new(x), Box::new(y), false) }
+			x:(@) sym("..=") y:@ { Expr::Range(Box::new(x), Box::new(y), true) }
+			--
 			x:(@) sym("and") y:@ { Expr::BinOp(BinOp::And, Box::new(x), Box::new(y)) }
 			x:(@) sym("or") y:@  { Expr::BinOp(BinOp::Or,  Box::new(x), Box::new(y)) }
 			--
@@ -66,18 +75,32 @@ peg::parser! {
 			x:(@) sym("==") y:@ { Expr::BinOp(BinOp::Equal, Box::new(x), Box::new(y)) }
 			x:(@) sym("!=") y:@ { Expr::BinOp(BinOp::NEq, Box::new(x), Box::new(y)) }
 			--
+			x:(@) sym("|") y:@ { Expr::BinOp(BinOp::BitOr, Box::new(x), Box::new(y)) }
+			--
+			x:(@) sym("~") y:@ { Expr::BinOp(BinOp::BitXor, Box::new(x), Box::new(y)) }
+			--
+			x:(@) sym("&") y:@ { Expr::BinOp(BinOp::BitAnd, Box::new(x), Box::new(y)) }
+			--
+			x:(@) sym("<<") y:@ { Expr::BinOp(BinOp::ShiftLeft,  Box::new(x), Box::new(y)) }
+			x:(@) shift_right(pos) y:@ { Expr::BinOp(BinOp::ShiftRight, Box::new(x), Box::new(y)) }
+			--
 			x:(@) sym("+") y:@ { Expr::BinOp(BinOp::Plus,  Box::new(x), Box::new(y)) }
 			x:(@) sym("-") y:@ { Expr::BinOp(BinOp::Minus, Box::new(x), Box::new(y)) }
 			--
 			sym("-") x:@ { Expr::UnaOp(UnaOp::Minus, Box::new(x)) }
+			sym("~") x:@ { Expr::UnaOp(UnaOp::BitNot, Box::new(x)) }
 			--
 			x:(@) sym("*") y:@ { Expr::BinOp(BinOp::Times,   Box::new(x), Box::new(y)) }
 			x:(@) sym("/") y:@ { Expr::BinOp(BinOp::Divides, Box::new(x), Box::new(y)) }
+			x:(@) sym("//") y:@ { Expr::BinOp(BinOp::IntDivides, Box::new(x), Box::new(y)) }
 			x:(@) sym("%") y:@ { Expr::BinOp(BinOp::Modulo,  Box::new(x), Box::new(y)) }
 			--
 			x:@ sym("^") y:(@) { Expr::BinOp(BinOp::Power,   Box::new(x), Box::new(y)) }
 			--
 			x:@ sym("[") i:expression(pos) sym("]") { Expr::Index(Box::new(x), Box::new(i)) }
+			x:@ sym("[") a:expression(pos)? sym(":") b:expression(pos)? sym("]") {
+				Expr::Slice(Box::new(x), a.map(Box::new), b.map(Box::new))
+			}
 			f:@ sym("(") args:(expression(pos) ** sym(",")) sym(",")? sym(")") { Expr::Call(Box::new(f), args) }
 			x:@ sym(".") p:identifier() { Expr::Prop(Box::new(x), p) }
 			--
@@ -85,7 +108,8 @@ peg::parser! {
 		}
 		
 		rule type_desc() -> Type
-			= t:identifier() { Type::Named(t) }
+			= t:identifier() sym("<") p:(type_desc() ++ sym(",")) sym(">") { Type::Parametric(t, p) }
+			/ t:identifier() { Type::Named(t) }
 		rule typed_ident() -> (String, Option<Type>)
 			= i:identifier() sym(":") t:type_desc() { (i, Some(t)) }
 			/ i:identifier() { (i, None) }
@@ -117,6 +141,7 @@ peg::parser! {
 				Stat::Cond(branches)
 			}
 			/ sym("return") e:expression(pos)? { Stat::Return(e.unwrap_or(Expr::Nil)) }
+			/ sym("yield") e:expression(pos)? { Stat::Yield(e.unwrap_or(Expr::Nil)) }
 			/ sym("while") e:expression(pos) b:indented_block(pos) { Stat::While(e, b) }
 			/ e:expression(pos) a:assignment(pos)? {?
 				if let Some(assigned) = a {
@@ -1,17 +1,18 @@
 
 use std::str::CharIndices;
-use std::iter::{Peekable, FromIterator};
+use std::iter::Peekable;
 use std::ops::Deref;
 use std::fmt;
 use unicode_xid::UnicodeXID;
 use peg::{Parse, ParseElem, ParseLiteral, ParseSlice, RuleResult, str::LineCol};
 use smallstr::SmallString;
+use num_bigint::BigInt;
 
 use crate::{HissyError, ErrorType};
 
 
 fn error(s: String, pos: LineCol) -> HissyError {
-	HissyError(ErrorType::Syntax, s, pos.line as u16)
+	HissyError::new(ErrorType::Syntax, s, pos.line as u16)
 }
 fn error_str(s: &str, pos: LineCol) -> HissyError {
 	error(String::from(s), pos)
@@ -24,18 +25,19 @@ type SymbolStr = SmallString<[u8;6]>;
 pub enum Token {
 	Symbol(SymbolStr),
 	Id(String),
-	Int(i32),
+	Int(i64),
+	BigInt(BigInt),
 	Real(f64),
 	String(String),
 	Newline, Indent, Dedent,
 	EOF,
 }
 
-static KEYWORDS: [&str; 15] = [
+static KEYWORDS: [&str; 16] = [
 	"let", "if", "else", "while", "for", "in",
 	"not", "and", "or",
 	"nil", "true", "false",
-	"return",
+	"return", "yield",
 	"fun",
 	"pass",
 ];
@@ -46,15 +48,19 @@ fn is_keyword(s: &str) -> bool {
 
 fn parse_number(input: &str, is_integer: bool) -> Token {
 	if is_integer {
-		if let Ok(i) = input.parse::<i32>() {
-			return Token::Int(i);
+		if let Ok(i) = input.parse::<i64>() {
+			Token::Int(i)
+		} else {
+			Token::BigInt(input.parse::<BigInt>().expect("Error while parsing integer literal"))
 		}
+	} else {
+		Token::Real(input.parse::<f64>().expect("Error while parsing real literal"))
 	}
-	Token::Real(input.parse::<f64>().expect("Error while parsing real literal"))
 }
 
-static SIMPLE_SYMBOLS: [char; 17] = [
+static SIMPLE_SYMBOLS: [char; 20] = [
 	'+', '-', '*', '/', '^', '%',
+	'&', '|', '~',
 	'=', '<', '>',
 	',', '(', ')', ':',
 	'[', ']',
@@ -62,17 +68,17 @@ static SIMPLE_SYMBOLS: [char; 17] = [
 	'\n',
 ];
 
-static SYMBOL_START: [char; 11] = [
+static SYMBOL_START: [char; 12] = [
 	'+', '-', '*', '/', '^', '%',
 	'=', '<', '>',
-	'!',
+	'!', '.',
 	'\r',
 ];
 
-static COMPLEX_SYMBOLS: [&str; 21] = [
+static COMPLEX_SYMBOLS: [&str; 25] = [
 	"=", "+", "-", "*", "/", "^", "%", "<", ">",
 	"==", "!=", "+=", "-=", "*=", "/=", "^=", "%=", "<=", ">=",
-	"->",
+	"->", "..", "..=", "//", "<<",
 	"\r\n",
 ];
 
@@ -83,16 +89,18 @@ fn parse_symbol(it: &mut Peekable<CharIndices>, c: char) -> Option<SymbolStr> {
 	if !simple && !start { return None; }
 	it.next(); // it has to be part of a symbol, consume c.
 	
+	let mut sym = String::from(c);
 	if start {
-		if let Some(pair) = it.peek().map(|(_,c2)| String::from_iter(&[c, *c2]))
-				.filter(|p| COMPLEX_SYMBOLS.contains(&p.deref())) {
-			it.next(); // consume second character
-			return Some(SmallString::from(pair));
+		// extend the symbol for as long as it stays a complex symbol
+		while let Some(longer) = it.peek().map(|(_,c2)| format!("{}{}", sym, c2))
+				.filter(|s| COMPLEX_SYMBOLS.contains(&s.deref())) {
+			it.next(); // consume next character
+			sym = longer;
 		}
 	}
 	
-	// if we get here, it has to be a simple symbol
-	Some(SmallString::from(c))
+	// if the symbol was not extended, it has to be a simple symbol
+	Some(SmallString::from(sym))
 }
 
 fn test_next_char<P>(it: &mut Peekable<CharIndices>, pred: &P) -> bool where P: Fn(char) -> bool {
@@ -200,7 +208,8 @@ pub fn read_tokens(input: &str) -> Result<Tokens, HissyError> {
 				let start = i;
 				let mut is_integer = true;
 				skip_chars(&mut it, &|c| c.is_ascii_digit());
-				if test_next_char(&mut it, &|c| c == '.') {
+				let after_digits = get_next_index(&mut it, input.len());
+				if test_next_char(&mut it, &|c| c == '.') && !input[after_digits..].starts_with("..") { // 1..2 is a range
 					is_integer = false;
 					it.next();
 					skip_chars(&mut it, &|c| c.is_ascii_digit());
@@ -14,7 +14,7 @@ pub fn parse(input: &str) -> Result<ast::ProgramAST, HissyError> {
 	let tokens = lexer::read_tokens(input)?;
 	peg_parser::program(&tokens, &tokens.token_pos).map_err(|err| {
 		let err_str = format!("Near {:?}, expected {}", err.location.near, err.expected);
-		HissyError(ErrorType::Syntax, err_str, err.location.line)
+		HissyError::new(ErrorType::Syntax, err_str, err.location.line)
 	})
 }
 
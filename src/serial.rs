@@ -6,7 +6,7 @@ use crate::{HissyError, ErrorType};
 
 
 fn error_str(s: &str) -> HissyError {
-	HissyError(ErrorType::IO, String::from(s), 0)
+	HissyError::new(ErrorType::IO, String::from(s), 0)
 }
 fn eof() -> HissyError {
 	error_str("Unexpected EOF")
@@ -50,6 +50,7 @@ serialize_numeric!(read_i8, write_i8, write_into_i8, i8);
 serialize_numeric!(read_u16, write_u16, write_into_u16, u16);
 serialize_numeric!(read_u32, write_u32, write_into_u32, u32);
 serialize_numeric!(read_i32, write_i32, write_into_i32, i32);
+serialize_numeric!(read_i64, write_i64, write_into_i64, i64);
 serialize_numeric!(read_f64, write_f64, write_into_f64, f64);
 
 
@@ -0,0 +1,313 @@
+
+use std::cell::Cell;
+use std::convert::TryFrom;
+
+use crate::{HissyError, ErrorType};
+use super::VMHandle;
+use super::value::Value;
+use super::object::{GCIterator, List};
+
+fn error(s: String) -> HissyError {
+	HissyError::new(ErrorType::Execution, s, 0)
+}
+
+pub fn call_predicate(vm: &mut VMHandle, pred: &Value, args: Vec<Value>) -> Result<bool, HissyError> {
+	let res = vm.call(pred, args)?;
+	bool::try_from(&res).map_err(|_| error(format!("Expected predicate to return a boolean, got {}", res.repr())))
+}
+
+pub fn get_count(val: &Value) -> Result<usize, HissyError> {
+	i64::try_from(val).ok().and_then(|n| usize::try_from(n).ok())
+		.ok_or_else(|| error(format!("Expected a non-negative count, got {}", val.repr())))
+}
+
+// Lazy iterator adaptors: each holds the inner iterators/functions as Values,
+// and only pulls from them when asked for the next element.
+
+pub struct Map {
+	inner: Value,
+	func: Value,
+}
+
+impl Map {
+	pub fn new(inner: Value, func: Value) -> Map {
+		Map { inner, func }
+	}
+}
+
+impl GCIterator for Map {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		match vm.next(&self.inner)? {
+			Some(val) => vm.call(&self.func, vec![val]).map(Some),
+			None => Ok(None),
+		}
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.inner.touch(initial);
+		self.func.touch(initial);
+	}
+}
+
+pub struct Filter {
+	inner: Value,
+	pred: Value,
+}
+
+impl Filter {
+	pub fn new(inner: Value, pred: Value) -> Filter {
+		Filter { inner, pred }
+	}
+}
+
+impl GCIterator for Filter {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		while let Some(val) = vm.next(&self.inner)? {
+			if call_predicate(vm, &self.pred, vec![val.clone()])? {
+				return Ok(Some(val));
+			}
+		}
+		Ok(None)
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.inner.touch(initial);
+		self.pred.touch(initial);
+	}
+}
+
+pub struct Enumerate {
+	inner: Value,
+	next_idx: Cell<i64>,
+}
+
+impl Enumerate {
+	pub fn new(inner: Value) -> Enumerate {
+		Enumerate { inner, next_idx: Cell::new(0) }
+	}
+}
+
+impl GCIterator for Enumerate {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		match vm.next(&self.inner)? {
+			Some(val) => {
+				let idx = self.next_idx.get();
+				self.next_idx.set(idx + 1);
+				let pair = List::new();
+				pair.extend(&[Value::from_i64(idx, vm.heap), val]);
+				Ok(Some(vm.heap.make_value(pair)))
+			},
+			None => Ok(None),
+		}
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.inner.touch(initial);
+	}
+}
+
+pub struct Zip {
+	first: Value,
+	second: Value,
+}
+
+impl Zip {
+	pub fn new(first: Value, second: Value) -> Zip {
+		Zip { first, second }
+	}
+}
+
+impl GCIterator for Zip {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		let val1 = match vm.next(&self.first)? {
+			Some(val) => val,
+			None => return Ok(None),
+		};
+		let val2 = match vm.next(&self.second)? {
+			Some(val) => val,
+			None => return Ok(None),
+		};
+		let pair = List::new();
+		pair.extend(&[val1, val2]);
+		Ok(Some(vm.heap.make_value(pair)))
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.first.touch(initial);
+		self.second.touch(initial);
+	}
+}
+
+pub struct Take {
+	inner: Value,
+	left: Cell<usize>,
+}
+
+impl Take {
+	pub fn new(inner: Value, n: usize) -> Take {
+		Take { inner, left: Cell::new(n) }
+	}
+}
+
+impl GCIterator for Take {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		let left = self.left.get();
+		if left == 0 {
+			return Ok(None);
+		}
+		self.left.set(left - 1);
+		vm.next(&self.inner)
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.inner.touch(initial);
+	}
+}
+
+pub struct Skip {
+	inner: Value,
+	to_skip: Cell<usize>,
+}
+
+impl Skip {
+	pub fn new(inner: Value, n: usize) -> Skip {
+		Skip { inner, to_skip: Cell::new(n) }
+	}
+}
+
+impl GCIterator for Skip {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		while self.to_skip.get() > 0 {
+			self.to_skip.set(self.to_skip.get() - 1);
+			if vm.next(&self.inner)?.is_none() {
+				return Ok(None);
+			}
+		}
+		vm.next(&self.inner)
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.inner.touch(initial);
+	}
+}
+
+pub struct Chain {
+	first: Value,
+	second: Value,
+	first_done: Cell<bool>,
+}
+
+impl Chain {
+	pub fn new(first: Value, second: Value) -> Chain {
+		Chain { first, second, first_done: Cell::new(false) }
+	}
+}
+
+impl GCIterator for Chain {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		if !self.first_done.get() {
+			if let Some(val) = vm.next(&self.first)? {
+				return Ok(Some(val));
+			}
+			self.first_done.set(true);
+		}
+		vm.next(&self.second)
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.first.touch(initial);
+		self.second.touch(initial);
+	}
+}
+
+pub struct TakeWhile {
+	inner: Value,
+	pred: Value,
+	done: Cell<bool>,
+}
+
+impl TakeWhile {
+	pub fn new(inner: Value, pred: Value) -> TakeWhile {
+		TakeWhile { inner, pred, done: Cell::new(false) }
+	}
+}
+
+impl GCIterator for TakeWhile {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		if self.done.get() {
+			return Ok(None);
+		}
+		if let Some(val) = vm.next(&self.inner)? {
+			if call_predicate(vm, &self.pred, vec![val.clone()])? {
+				return Ok(Some(val));
+			}
+		}
+		self.done.set(true);
+		Ok(None)
+	}
+	
+	fn touch(&self, initial: bool) {
+		self.inner.touch(initial);
+		self.pred.touch(initial);
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::ErrorType;
+	use super::super::tests::eval;
+	
+	const HELPERS: &str = "let double(x: Int) -> Int:
+	return x * 2
+let small(x: Int) -> Bool:
+	return x < 3
+let mul(acc: Int, x: Int) -> Int:
+	return acc * x
+let naturals() -> Iterator<Int>:
+	let i = 0
+	while true:
+		yield i
+		i = i + 1
+";
+	
+	fn check(cases: &[(&str, &str)]) {
+		for (code, expected) in cases {
+			assert_eq!(eval(&[HELPERS, code].concat()).unwrap(), *expected, "{}", code);
+		}
+	}
+	
+	#[test]
+	fn test_adaptors() {
+		check(&[
+			("let l: List<Int> = range(0, 4).map(double).collect()\nreturn l\n", "[0, 2, 4, 6]"),
+			("return range(0, 6).filter(small).collect()\n", "[0, 1, 2]"),
+			("return [\"a\", \"b\"].iter().enumerate().collect()\n", "[[0, \"a\"], [1, \"b\"]]"),
+			("return range(0, 3).zip(range(10, 12)).collect()\n", "[[0, 10], [1, 11]]"),
+			("return range(0, 5).skip(3).chain(range(7, 9)).collect()\n", "[3, 4, 7, 8]"),
+			// Adaptors are lazy, so they work on infinite generators
+			("return naturals().map(double).skip(1).take(3).collect()\n", "[2, 4, 6]"),
+			("return naturals().take_while(small).collect()\n", "[0, 1, 2]"),
+		]);
+		
+		assert_eq!(eval("return range(0, 3).take(0 - 1).collect()\n").unwrap_err().1, "Expected a non-negative count, got -1");
+		// Element types are propagated through adaptors
+		let err = eval(&[HELPERS, "let l: List<String> = range(0, 3).map(double).collect()\n"].concat()).unwrap_err();
+		assert!(matches!(err.0, ErrorType::Compilation));
+	}
+	
+	#[test]
+	fn test_terminal_operations() {
+		check(&[
+			("return [range(0, 9).filter(small).count(), range(0, 0).sum(), range(1, 5).sum()]\n", "[3, 0, 10]"),
+			("return [range(1, 5).fold(1, mul), range(0, 3).any(small), range(0, 3).all(small), range(0, 4).all(small)]\n",
+				"[24, true, true, false]"),
+			("let l = []\nnaturals().take(3).for_each(l.add)\nreturn l\n", "[0, 1, 2]"),
+			// Generators keep their upvalues when driven from native code
+			("let k = 10\nlet gen() -> Iterator<Int>:\n\tfor i in range(0, 2):\n\t\tk = k + 1\n\t\tyield k\nreturn [gen().map(double).collect(), k]\n",
+				"[[22, 24], 12]"),
+		]);
+		
+		assert_eq!(eval("return [1, \"a\"].iter().sum()\n").unwrap_err().1, "Cannot add these values");
+	}
+}
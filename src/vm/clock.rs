@@ -0,0 +1,170 @@
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{HissyError, ErrorType};
+
+fn error(s: String) -> HissyError {
+	HissyError::new(ErrorType::Execution, s, 0)
+}
+
+/// The source of time for the `time` module.
+///
+/// Hosts can replace the default [`SystemClock`] with [`VM::set_clock`](super::VM::set_clock),
+/// for instance with a [`FakeClock`] to make tests deterministic.
+pub trait Clock {
+	/// Seconds elapsed since an arbitrary origin. Must never go backwards.
+	fn now(&self) -> f64;
+	/// Seconds elapsed since the Unix epoch.
+	fn wall(&self) -> f64;
+	/// Blocks for the given non-negative number of seconds.
+	fn sleep(&mut self, secs: f64);
+}
+
+/// The real clock of the system, measuring `now` from its creation.
+pub struct SystemClock {
+	start: Instant,
+}
+
+impl SystemClock {
+	pub fn new() -> SystemClock {
+		SystemClock { start: Instant::now() }
+	}
+}
+
+impl Default for SystemClock {
+	fn default() -> SystemClock {
+		SystemClock::new()
+	}
+}
+
+impl Clock for SystemClock {
+	fn now(&self) -> f64 {
+		self.start.elapsed().as_secs_f64()
+	}
+	
+	fn wall(&self) -> f64 {
+		match SystemTime::now().duration_since(UNIX_EPOCH) {
+			Ok(d) => d.as_secs_f64(),
+			Err(e) => -e.duration().as_secs_f64(),
+		}
+	}
+	
+	fn sleep(&mut self, secs: f64) {
+		thread::sleep(Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX));
+	}
+}
+
+/// A clock which only moves when told to, or when the script sleeps.
+///
+/// Clones share the same time, so the host can keep one to advance the clock given to a VM.
+#[derive(Clone)]
+pub struct FakeClock {
+	elapsed: Rc<Cell<f64>>,
+	wall_origin: f64,
+}
+
+impl FakeClock {
+	/// Creates a clock at `now() == 0`, with `wall_origin` as its wall-clock time.
+	pub fn new(wall_origin: f64) -> FakeClock {
+		FakeClock { elapsed: Rc::new(Cell::new(0.0)), wall_origin }
+	}
+	
+	pub fn advance(&self, secs: f64) {
+		self.elapsed.set(self.elapsed.get() + secs);
+	}
+}
+
+impl Clock for FakeClock {
+	fn now(&self) -> f64 {
+		self.elapsed.get()
+	}
+	
+	fn wall(&self) -> f64 {
+		self.wall_origin + self.elapsed.get()
+	}
+	
+	fn sleep(&mut self, secs: f64) {
+		self.advance(secs);
+	}
+}
+
+
+// Converts a number of days since the Unix epoch into a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+	let z = days + 719_468; // Count from 0000-03-01, so that leap days end the year
+	let era = z.div_euclid(146_097);
+	let doe = z.rem_euclid(146_097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
+
+/// Formats a wall-clock time, in seconds since the Unix epoch, as a UTC date.
+///
+/// Supports the specifiers `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` and `%%`.
+pub fn format_time(secs: f64, fmt: &str) -> Result<String, HissyError> {
+	if !secs.is_finite() || secs.abs() > 1e15 {
+		return Err(error(format!("Cannot format time {}", secs)));
+	}
+	let secs = secs.floor() as i64;
+	let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+	let time = secs.rem_euclid(86400);
+	
+	let mut res = String::new();
+	let mut chars = fmt.chars();
+	while let Some(c) = chars.next() {
+		if c != '%' {
+			res.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('Y') => res.push_str(&format!("{:04}", year)),
+			Some('m') => res.push_str(&format!("{:02}", month)),
+			Some('d') => res.push_str(&format!("{:02}", day)),
+			Some('H') => res.push_str(&format!("{:02}", time / 3600)),
+			Some('M') => res.push_str(&format!("{:02}", time / 60 % 60)),
+			Some('S') => res.push_str(&format!("{:02}", time % 60)),
+			Some('%') => res.push('%'),
+			Some(c) => return Err(error(format!("Unknown time format specifier %{}", c))),
+			None => return Err(error(String::from("Time format ends with a lone %"))),
+		}
+	}
+	Ok(res)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{FakeClock, format_time};
+	use crate::compiler::Compiler;
+	use crate::vm::{VM, OutputBuffer, gc::GCHeap};
+	
+	#[test]
+	fn test_format() {
+		assert_eq!(format_time(0.0, "%Y-%m-%d %H:%M:%S").unwrap(), "1970-01-01 00:00:00");
+		assert_eq!(format_time(951_827_696.5, "%Y-%m-%d %H:%M:%S").unwrap(), "2000-02-29 12:34:56");
+		assert_eq!(format_time(-1.0, "%d/%m/%Y %%").unwrap(), "31/12/1969 %");
+		assert!(format_time(0.0, "%x").is_err());
+	}
+	
+	#[test]
+	fn test_fake_clock() {
+		let program = Compiler::new(false).compile_program("time.sleep(1.5)\nlog(time.now(), time.format(time.wall(), \"%H:%M:%S\"))\n").unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		let output = OutputBuffer::new();
+		vm.set_output(Box::new(output.clone()));
+		let clock = FakeClock::new(3600.0);
+		clock.advance(60.0);
+		vm.set_clock(Box::new(clock.clone()));
+		vm.run_main(&mut heap).unwrap();
+		assert_eq!(output.contents(), "61.5 \"01:01:01\"\n");
+	}
+}
@@ -0,0 +1,63 @@
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::{HissyError, ErrorType};
+
+pub fn io_error(err: io::Error) -> HissyError {
+	HissyError::new(ErrorType::IO, format!("Console I/O failed: {}", err), 0)
+}
+
+/// An output sink which keeps everything written to it.
+///
+/// Clones share the same buffer, so the host can give one to a [`VM`](super::VM)
+/// and read the script's output back through another.
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+	pub fn new() -> OutputBuffer {
+		OutputBuffer::default()
+	}
+	
+	/// Returns everything written so far, replacing invalid UTF-8.
+	pub fn contents(&self) -> String {
+		String::from_utf8_lossy(&self.0.borrow()).into_owned()
+	}
+	
+	pub fn clear(&self) {
+		self.0.borrow_mut().clear();
+	}
+}
+
+impl Write for OutputBuffer {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().extend_from_slice(buf);
+		Ok(buf.len())
+	}
+	
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::OutputBuffer;
+	use crate::compiler::Compiler;
+	use crate::vm::{VM, gc::GCHeap};
+	
+	#[test]
+	fn test_capture() {
+		let program = Compiler::new(false).compile_program("log(1, \"a\")\nprint(\"b\", input(\"?\"))\n").unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		let output = OutputBuffer::new();
+		vm.set_output(Box::new(output.clone()));
+		vm.set_input(Box::new("c\nd\n".as_bytes()));
+		vm.run_main(&mut heap).unwrap();
+		assert_eq!(output.contents(), "1 \"a\"\n?b c");
+	}
+}
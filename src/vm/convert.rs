@@ -0,0 +1,292 @@
+
+use std::convert::TryFrom;
+
+use crate::{prim_ty, HissyError, ErrorType};
+use crate::compiler::{Type, PrimitiveType};
+use super::gc::{GC, GCRef, GCHeap};
+use super::value::{Value, NIL};
+use super::object::{List, Map, Coroutine};
+
+fn error(s: String) -> HissyError {
+	HissyError::new(ErrorType::Execution, s, 0)
+}
+
+fn mismatch<T: HissyType>(val: &Value) -> HissyError {
+	error(format!("Expected {:?}, got {}", T::hissy_type(), val.repr()))
+}
+
+fn int_mismatch(val: &Value, bits: u8) -> HissyError {
+	if val.is_int() {
+		error(format!("Expected an Int fitting in {} bits, got {}", bits, val.repr()))
+	} else {
+		mismatch::<i64>(val)
+	}
+}
+
+/// A Rust type with a static Hissy type, used to derive the signature of host functions.
+pub trait HissyType {
+	fn hissy_type() -> Type;
+}
+
+/// Conversion of a Hissy value into a Rust type, used for the arguments of host functions.
+pub trait FromValue: HissyType + Sized {
+	fn from_value(val: &Value) -> Result<Self, HissyError>;
+}
+
+/// Conversion of a Rust type into a Hissy value, used for the results of host functions.
+pub trait IntoValue: HissyType {
+	fn into_value(self, heap: &mut GCHeap) -> Value;
+}
+
+
+impl HissyType for Value {
+	fn hissy_type() -> Type { Type::Any }
+}
+impl FromValue for Value {
+	fn from_value(val: &Value) -> Result<Value, HissyError> {
+		Ok(val.clone())
+	}
+}
+impl IntoValue for Value {
+	fn into_value(self, _heap: &mut GCHeap) -> Value {
+		self
+	}
+}
+
+impl HissyType for () {
+	fn hissy_type() -> Type { prim_ty!(Nil) }
+}
+impl IntoValue for () {
+	fn into_value(self, _heap: &mut GCHeap) -> Value {
+		NIL
+	}
+}
+
+impl HissyType for bool {
+	fn hissy_type() -> Type { prim_ty!(Bool) }
+}
+impl FromValue for bool {
+	fn from_value(val: &Value) -> Result<bool, HissyError> {
+		bool::try_from(val).map_err(|_| mismatch::<bool>(val))
+	}
+}
+impl IntoValue for bool {
+	fn into_value(self, _heap: &mut GCHeap) -> Value {
+		Value::from(self)
+	}
+}
+
+impl HissyType for i32 {
+	fn hissy_type() -> Type { prim_ty!(Int) }
+}
+impl FromValue for i32 {
+	fn from_value(val: &Value) -> Result<i32, HissyError> {
+		i32::try_from(val).map_err(|_| int_mismatch(val, 32))
+	}
+}
+impl IntoValue for i32 {
+	fn into_value(self, _heap: &mut GCHeap) -> Value {
+		Value::from(self)
+	}
+}
+
+impl HissyType for i64 {
+	fn hissy_type() -> Type { prim_ty!(Int) }
+}
+impl FromValue for i64 {
+	fn from_value(val: &Value) -> Result<i64, HissyError> {
+		i64::try_from(val).map_err(|_| int_mismatch(val, 64))
+	}
+}
+impl IntoValue for i64 {
+	fn into_value(self, heap: &mut GCHeap) -> Value {
+		Value::from_i64(self, heap)
+	}
+}
+
+impl HissyType for f64 {
+	fn hissy_type() -> Type { prim_ty!(Real) }
+}
+impl FromValue for f64 {
+	fn from_value(val: &Value) -> Result<f64, HissyError> {
+		f64::try_from(val).map_err(|_| mismatch::<f64>(val))
+	}
+}
+impl IntoValue for f64 {
+	fn into_value(self, _heap: &mut GCHeap) -> Value {
+		Value::from(self)
+	}
+}
+
+impl HissyType for String {
+	fn hissy_type() -> Type { prim_ty!(String) }
+}
+impl FromValue for String {
+	fn from_value(val: &Value) -> Result<String, HissyError> {
+		let s = GCRef::<String>::try_from(val.clone()).map_err(|_| mismatch::<String>(val))?;
+		Ok((*s).clone())
+	}
+}
+impl IntoValue for String {
+	fn into_value(self, heap: &mut GCHeap) -> Value {
+		heap.make_value(self)
+	}
+}
+
+impl HissyType for List {
+	fn hissy_type() -> Type { Type::List(Box::new(Type::Any)) }
+}
+impl HissyType for Coroutine {
+	fn hissy_type() -> Type { Type::Coroutine }
+}
+impl HissyType for Map {
+	fn hissy_type() -> Type { Type::Map }
+}
+
+/// Gives access to the object itself, without copying it.
+impl<T: GC + HissyType> HissyType for GCRef<T> {
+	fn hissy_type() -> Type { T::hissy_type() }
+}
+impl<T: GC + HissyType> FromValue for GCRef<T> {
+	fn from_value(val: &Value) -> Result<GCRef<T>, HissyError> {
+		GCRef::<T>::try_from(val.clone()).map_err(|_| mismatch::<T>(val))
+	}
+}
+impl<T: GC + HissyType> IntoValue for GCRef<T> {
+	fn into_value(self, _heap: &mut GCHeap) -> Value {
+		Value::from(self)
+	}
+}
+
+/// Converts from and to a list, copying its elements.
+impl<T: HissyType> HissyType for Vec<T> {
+	fn hissy_type() -> Type { Type::List(Box::new(T::hissy_type())) }
+}
+impl<T: FromValue> FromValue for Vec<T> {
+	fn from_value(val: &Value) -> Result<Vec<T>, HissyError> {
+		let list = GCRef::<List>::try_from(val.clone()).map_err(|_| mismatch::<Vec<T>>(val))?;
+		list.get_copy().iter().map(T::from_value).collect()
+	}
+}
+impl<T: IntoValue> IntoValue for Vec<T> {
+	fn into_value(self, heap: &mut GCHeap) -> Value {
+		let values: Vec<Value> = self.into_iter().map(|x| x.into_value(heap)).collect();
+		let list = List::new();
+		list.extend(&values);
+		heap.make_value(list)
+	}
+}
+
+/// `None` is `nil`. Since Hissy has no optional types, this is typed `Any`.
+impl<T> HissyType for Option<T> {
+	fn hissy_type() -> Type { Type::Any }
+}
+impl<T: FromValue> FromValue for Option<T> {
+	fn from_value(val: &Value) -> Result<Option<T>, HissyError> {
+		if val.is_nil() { Ok(None) } else { T::from_value(val).map(Some) }
+	}
+}
+impl<T: IntoValue> IntoValue for Option<T> {
+	fn into_value(self, heap: &mut GCHeap) -> Value {
+		self.map_or(NIL, |x| x.into_value(heap))
+	}
+}
+
+
+/// The result of a host function: either a value, or a `Result` to report errors to the script.
+pub trait IntoResult {
+	fn result_type() -> Type;
+	fn into_result(self, heap: &mut GCHeap) -> Result<Value, HissyError>;
+}
+
+impl<T: IntoValue> IntoResult for T {
+	fn result_type() -> Type { T::hissy_type() }
+	fn into_result(self, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		Ok(self.into_value(heap))
+	}
+}
+
+impl<T: IntoValue> IntoResult for Result<T, HissyError> {
+	fn result_type() -> Type { T::hissy_type() }
+	fn into_result(self, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		self.map(|x| x.into_value(heap))
+	}
+}
+
+/// A Rust function which can be called from Hissy, converting its arguments and result.
+///
+/// It is implemented for functions and closures of up to 6 arguments, whose arguments implement
+/// [`FromValue`] and whose result implements [`IntoResult`]. `Args` is the tuple of argument types.
+pub trait HostFunction<Args>: 'static {
+	/// The Hissy type of the function, derived from its Rust signature.
+	fn hissy_type() -> Type;
+	fn call(&self, heap: &mut GCHeap, args: Vec<Value>) -> Result<Value, HissyError>;
+}
+
+macro_rules! impl_host_function {
+	($nb_args:literal $(, $arg:ident)*) => {
+		impl<Fun, Res, $($arg),*> HostFunction<($($arg,)*)> for Fun
+				where Fun: Fn($($arg),*) -> Res + 'static, Res: IntoResult, $($arg: FromValue),* {
+			fn hissy_type() -> Type {
+				Type::TypedFunction(vec![$($arg::hissy_type()),*], Box::new(Res::result_type()))
+			}
+			
+			#[allow(non_snake_case, unused_variables, unused_mut)]
+			fn call(&self, heap: &mut GCHeap, args: Vec<Value>) -> Result<Value, HissyError> {
+				if args.len() != $nb_args {
+					return Err(error(format!("Expected {} arguments in function call, got {}", $nb_args, args.len())));
+				}
+				let mut args = args.iter().enumerate();
+				$(
+					let (i, val) = args.next().unwrap();
+					let $arg = $arg::from_value(val).map_err(|e| error(format!("Invalid argument {}: {}", i + 1, e.1)))?;
+				)*
+				self($($arg),*).into_result(heap)
+			}
+		}
+	};
+}
+
+impl_host_function!(0);
+impl_host_function!(1, A);
+impl_host_function!(2, A, B);
+impl_host_function!(3, A, B, C);
+impl_host_function!(4, A, B, C, D);
+impl_host_function!(5, A, B, C, D, E);
+impl_host_function!(6, A, B, C, D, E, F);
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{HissyError, ErrorType};
+	use crate::compiler::Compiler;
+	use crate::vm::{VM, Environment, gc::GCHeap};
+	
+	fn repeat(s: String, n: i32) -> Result<Vec<String>, HissyError> {
+		if n < 0 {
+			return Err(HissyError::new(ErrorType::Execution, String::from("Negative count"), 0));
+		}
+		Ok(vec![s; n as usize])
+	}
+	
+	#[test]
+	fn test_host_function() {
+		let mut heap = GCHeap::new();
+		let mut env = Environment::new(&mut heap);
+		env.define_fn(&mut heap, "repeat", repeat).unwrap();
+		env.define_fn(&mut heap, "first", |l: Vec<i64>| l.first().copied()).unwrap();
+		let run = |heap: &mut GCHeap, code: &str| {
+			let program = Compiler::with_environment(false, &env).compile_program(code)?;
+			VM::with_environment(&program, &env).run_main(heap).map(|v| v.repr())
+		};
+		assert_eq!(run(&mut heap, "return repeat(\"ab\", 2)\n").unwrap(), "[\"ab\", \"ab\"]");
+		assert_eq!(run(&mut heap, "let l = [1]\nl.clear()\nreturn first(l)\n").unwrap(), "nil");
+		assert_eq!(run(&mut heap, "return first([3, 4])\n").unwrap(), "3");
+		let err = run(&mut heap, "let f: Int = repeat\n").unwrap_err();
+		assert_eq!(err.1, "Cannot define variable of type Int with expression of type (String, Int) -> List<String>");
+		assert!(run(&mut heap, "repeat(1, 2)\n").is_err()); // Type error
+		let err = run(&mut heap, "repeat(\"a\", 1 << 40)\n").unwrap_err();
+		assert_eq!(err.1, "Invalid argument 2: Expected an Int fitting in 32 bits, got 1099511627776");
+		assert_eq!(run(&mut heap, "repeat(\"a\", -1)\n").unwrap_err().1, "Negative count");
+	}
+}
@@ -0,0 +1,248 @@
+
+use std::convert::TryFrom;
+
+use crate::{HissyError, ErrorType};
+use crate::compiler::Type;
+use super::gc::{GC, GCHeap, GCRef};
+use super::value::Value;
+use super::object::{NativeFunction, Namespace, List};
+use super::{prelude, VMHandle, HostFunction, HissyType};
+
+fn error(s: String) -> HissyError {
+	HissyError::new(ErrorType::Compilation, s, 0)
+}
+
+// Type names which the compiler resolves by itself
+const BUILTIN_TYPES: &[&str] = &["Any", "Nil", "Bool", "Int", "Real", "String", "List", "Iterator", "Coroutine", "Map"];
+
+/// The external values a program can refer to: the prelude, followed by the functions,
+/// constants and modules registered by the host.
+///
+/// Compiled code refers to externals by index, so a program must be run with the environment
+/// it was compiled with (see [`Compiler::with_environment`](crate::compiler::Compiler::with_environment)
+/// and [`VM::with_environment`](super::VM::with_environment)). Its values live in the heap it was created with.
+pub struct Environment {
+	externals: Vec<(String, Type, Value)>,
+	object_types: Vec<String>,
+	args: GCRef<List>,
+	fs_allowed: bool,
+}
+
+impl Environment {
+	/// Creates an environment containing only the prelude.
+	pub fn new(heap: &mut GCHeap) -> Environment {
+		let (values, args) = prelude::create(heap);
+		let externals = prelude::list().into_iter().zip(values)
+			.map(|((name, ty), val)| (name, ty, val)).collect();
+		Environment { externals, object_types: vec![], args, fs_allowed: false }
+	}
+	
+	/// Makes a value available to scripts under a global name.
+	///
+	/// The compiler trusts `ty` to type-check uses of the value, so it must describe the value correctly.
+	pub fn define(&mut self, name: &str, ty: Type, value: Value) -> Result<(), HissyError> {
+		if self.externals.iter().any(|(name2, _, _)| name2 == name) {
+			return Err(error(format!("External {} is already defined", name)));
+		}
+		if u16::try_from(self.externals.len()).is_err() {
+			return Err(error(String::from("Too many externals")));
+		}
+		self.externals.push((String::from(name), ty, value));
+		Ok(())
+	}
+	
+	/// Makes a host function available to scripts, with the given parameter and return types.
+	pub fn define_function(&mut self, heap: &mut GCHeap, name: &str, params: Vec<Type>, ret: Type,
+			fun: impl Fn(&mut VMHandle, Vec<Value>) -> Result<Value, HissyError> + 'static) -> Result<(), HissyError> {
+		let nb_params = params.len();
+		let value = heap.make_value(NativeFunction::new(move |vm, args| {
+			// Functions can also be called through values of untyped function types
+			if args.len() != nb_params {
+				return Err(HissyError::new(ErrorType::Execution,
+					format!("Expected {} arguments in function call, got {}", nb_params, args.len()), 0));
+			}
+			fun(vm, args)
+		}));
+		self.define(name, Type::TypedFunction(params, Box::new(ret)), value)
+	}
+	
+	/// Makes a Rust function available to scripts, with a type derived from its signature.
+	/// 
+	/// For instance, a `fn(i32, String) -> bool` gets the type `(Int, String) -> Bool`.
+	pub fn define_fn<Args, F: HostFunction<Args>>(&mut self, heap: &mut GCHeap, name: &str, fun: F) -> Result<(), HissyError> {
+		let value = heap.make_value(NativeFunction::from_fn(fun));
+		self.define(name, F::hissy_type(), value)
+	}
+	
+	/// Makes a namespace of values available to scripts, as a module whose members are
+	/// accessed as properties, like `math.pi`.
+	pub fn define_module(&mut self, heap: &mut GCHeap, name: &str, members: Vec<(String, Type, Value)>) -> Result<(), HissyError> {
+		if u8::try_from(members.len()).is_err() {
+			return Err(error(format!("Module {} has too many members", name)));
+		}
+		let (types, values): (Vec<_>, Vec<_>) = members.into_iter().map(|(name, ty, val)| ((name, ty), val)).unzip();
+		let value = heap.make_value(Namespace(values));
+		self.define(name, Type::Module(types), value)
+	}
+	
+	/// Makes a Rust type available to scripts, with a namespace of methods callable on its objects.
+	/// 
+	/// `T::hissy_type()` must be a [`Type::Object`], whose name scripts can use in type annotations.
+	/// Each method receives the object as its first argument; see [`Environment::method`].
+	pub fn define_type<T: GC + HissyType>(&mut self, heap: &mut GCHeap, methods: Vec<(String, Type, Value)>) -> Result<(), HissyError> {
+		let name = if let Type::Object(name) = T::hissy_type() { name }
+			else { return Err(error(format!("Cannot define {:?} as an object type", T::hissy_type()))); };
+		if BUILTIN_TYPES.contains(&name.as_str()) {
+			return Err(error(format!("Type {} is already defined", name)));
+		}
+		if u8::try_from(methods.len()).is_err() {
+			return Err(error(format!("Type {} has too many methods", name)));
+		}
+		let (types, values): (Vec<_>, Vec<_>) = methods.into_iter().map(|(name, ty, val)| ((name, ty), val)).unzip();
+		let value = heap.make_value(Namespace(values));
+		self.define(&name, Type::Namespace(types), value)?;
+		self.object_types.push(name);
+		Ok(())
+	}
+	
+	/// Makes a method for [`Environment::define_type`] from a Rust function, whose first argument is the object.
+	pub fn method<Args, F: HostFunction<Args>>(heap: &mut GCHeap, name: &str, fun: F) -> Result<(String, Type, Value), HissyError> {
+		let ty = match F::hissy_type() {
+			Type::TypedFunction(mut params, ret) if !params.is_empty() => {
+				params.remove(0);
+				Type::TypedFunction(params, ret)
+			},
+			_ => return Err(error(format!("Method {} does not take the object as argument", name))),
+		};
+		Ok((String::from(name), ty, heap.make_value(NativeFunction::from_fn(fun))))
+	}
+	
+	/// Gives the scripts run in VMs created with this environment access to the file system.
+	/// 
+	/// It is disabled by default; [`VM::allow_fs`](super::VM::allow_fs) changes it for a single VM.
+	pub fn allow_fs(&mut self, allowed: bool) {
+		self.fs_allowed = allowed;
+	}
+	
+	pub(crate) fn args(&self) -> GCRef<List> {
+		self.args.clone()
+	}
+	
+	pub(crate) fn fs_allowed(&self) -> bool {
+		self.fs_allowed
+	}
+	
+	pub(crate) fn object_types(&self) -> &[String] {
+		&self.object_types
+	}
+	
+	pub(crate) fn types(&self) -> Vec<(String, Type)> {
+		self.externals.iter().map(|(name, ty, _)| (name.clone(), ty.clone())).collect()
+	}
+	
+	pub(crate) fn values(&self) -> Vec<Value> {
+		self.externals.iter().map(|(_, _, val)| val.clone()).collect()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::cell::Cell;
+	use std::convert::TryFrom;
+	
+	use super::Environment;
+	use crate::{prim_ty, compiler::{Compiler, Type, PrimitiveType}};
+	use crate::vm::{VM, OutputBuffer, HissyType, FromValue, gc::{GCHeap, GCRef, Traceable}, value::Value};
+	
+	#[derive(Debug)]
+	struct Player {
+		name: String,
+		score: Cell<i64>,
+	}
+	
+	impl Traceable for Player {}
+	
+	impl HissyType for Player {
+		fn hissy_type() -> Type { Type::Object(String::from("Player")) }
+	}
+	
+	#[derive(Debug)]
+	struct FakeMap;
+	
+	impl Traceable for FakeMap {}
+	
+	impl HissyType for FakeMap {
+		fn hissy_type() -> Type { Type::Object(String::from("Map")) }
+	}
+	
+	#[test]
+	fn test_host_definitions() {
+		let mut heap = GCHeap::new();
+		let mut env = Environment::new(&mut heap);
+		env.define_function(&mut heap, "twice", vec![prim_ty!(Int)], prim_ty!(Int), |vm, args| {
+			Ok(Value::from_i64(i64::try_from(&args[0]).unwrap() * 2, vm.heap))
+		}).unwrap();
+		let name = heap.make_value(String::from("test"));
+		env.define_module(&mut heap, "host", vec![
+			(String::from("name"), prim_ty!(String), name),
+			(String::from("ready"), prim_ty!(Bool), Value::from(true)),
+		]).unwrap();
+		assert!(env.define("twice", prim_ty!(Nil), Value::from(false)).is_err());
+		
+		let program = Compiler::with_environment(false, &env).compile_program("log(twice(21), host.name, host.ready)\n").unwrap();
+		let mut vm = VM::with_environment(&program, &env);
+		let output = OutputBuffer::new();
+		vm.set_output(Box::new(output.clone()));
+		vm.run_main(&mut heap).unwrap();
+		assert_eq!(output.contents(), "42 \"test\" true\n");
+		
+		assert!(Compiler::with_environment(false, &env).compile_program("twice(\"a\")\n").is_err());
+		assert!(Compiler::new(false).compile_program("twice(1)\n").is_err());
+	}
+	
+	#[test]
+	fn test_object_type() {
+		let mut heap = GCHeap::new();
+		let mut env = Environment::new(&mut heap);
+		let methods = vec![
+			Environment::method(&mut heap, "name", |this: GCRef<Player>| this.name.clone()).unwrap(),
+			Environment::method(&mut heap, "add_score", |this: GCRef<Player>, n: i64| {
+				this.score.set(this.score.get() + n);
+				this.score.get()
+			}).unwrap(),
+		];
+		env.define_type::<Player>(&mut heap, methods).unwrap();
+		env.define_function(&mut heap, "new_player", vec![prim_ty!(String)], Player::hissy_type(), |vm, args| {
+			let name = String::from_value(&args[0])?;
+			Ok(vm.heap.make_value(Player { name, score: Cell::new(0) }))
+		}).unwrap();
+		assert!(env.define_type::<Player>(&mut heap, vec![]).is_err());
+		assert_eq!(env.define_type::<FakeMap>(&mut heap, vec![]).unwrap_err().1, "Type Map is already defined");
+		
+		let compile = |code: &str| Compiler::with_environment(false, &env).compile_program(code);
+		let program = compile("let p: Player = new_player(\"bob\")\np.add_score(3)\nlet add = p.add_score\nadd(4)\nreturn p.name()\n").unwrap();
+		let mut vm = VM::with_environment(&program, &env);
+		assert_eq!(vm.run_main(&mut heap).unwrap().repr(), "\"bob\"");
+		let p = GCRef::<Player>::try_from(vm.get_global("p").unwrap()).unwrap();
+		assert_eq!(p.score.get(), 7);
+		
+		assert!(compile("new_player(\"a\").fly()\n").is_err());
+		assert!(compile("let p: Player = 1\n").is_err());
+		assert!(compile("let l: List<Player> = [new_player(\"a\")]\n").is_ok());
+	}
+	
+	#[test]
+	fn test_fs_access() {
+		let mut heap = GCHeap::new();
+		let mut env = Environment::new(&mut heap);
+		let program = Compiler::with_environment(false, &env).compile_program("return fs.exists(\".\")\n").unwrap();
+		assert_eq!(VM::with_environment(&program, &env).run_main(&mut heap).unwrap_err().1, "File system access is disabled");
+		env.allow_fs(true);
+		assert_eq!(VM::with_environment(&program, &env).run_main(&mut heap).unwrap().repr(), "true");
+		
+		let mut vm = VM::with_environment(&program, &env);
+		vm.allow_fs(false);
+		assert!(vm.run_main(&mut heap).is_err());
+	}
+}
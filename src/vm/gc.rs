@@ -7,6 +7,7 @@ use std::marker::PhantomData;
 use std::any::Any;
 use std::ops::Deref;
 
+use crate::{HissyError, ErrorType};
 use super::value::Value;
 
 
@@ -30,6 +31,10 @@ pub trait Traceable {
 	/// Should call .touch(initial) on all direct GCRef/Value children of self.
 	/// This is used for garbage collection.
 	fn touch(&self, _initial: bool) {}
+	
+	/// Should return the number of bytes owned by self outside of itself, like the buffer of a `Vec`.
+	/// This is used to account for the memory used by the heap.
+	fn heap_size(&self) -> usize { 0 }
 }
 
 /// An auto-implemented trait with all the supertraits required for GC values.
@@ -44,6 +49,7 @@ pub(super) struct GCWrapper_<T: ?Sized> {
 	vtable: *mut (),
 	marked: Cell<bool>,
 	roots: Cell<u32>,
+	size: Cell<usize>, // As of the last allocation, collection or size update
 	data: T,
 }
 pub(super) type GCWrapper = GCWrapper_<dyn GC>;
@@ -59,6 +65,7 @@ impl GCWrapper {
 			vtable: raw_object.vtable,
 			marked: Cell::new(false),
 			roots: Cell::new(0),
+			size: Cell::new(0),
 			data: value
 		})
 	}
@@ -88,8 +95,8 @@ impl GCWrapper {
 		format!("{:?}", self)
 	}
 	
-	fn size(&self) -> usize {
-		mem::size_of_val(&self) + mem::size_of_val(&self.data)
+	fn compute_size(&self) -> usize {
+		mem::size_of_val(self) + self.data.heap_size()
 	}
 	
 	pub fn signal_root(&self) {
@@ -205,6 +212,10 @@ impl<T: GC> Debug for GCRef<T> {
 
 const INIT_THRESHOLD: usize = 64;
 
+fn out_of_memory(used: usize, limit: usize) -> HissyError {
+	HissyError::new(ErrorType::Execution, format!("Out of memory: heap uses {} bytes, limit is {}", used, limit), 0)
+}
+
 /// Object maintaining all GC state.
 /// 
 /// Usually, only one should be created.
@@ -213,6 +224,7 @@ pub struct GCHeap {
 	objects: Vec<Pin<Box<GCWrapper>>>,
 	threshold: usize,
 	used: usize,
+	limit: Option<usize>,
 }
 
 impl GCHeap {
@@ -222,12 +234,14 @@ impl GCHeap {
 			objects: vec![],
 			threshold: INIT_THRESHOLD,
 			used: 0,
+			limit: None,
 		}
 	}
 	
 	fn add<T: GC>(&mut self, v: T) -> &GCWrapper {
 		let wrapper = GCWrapper::new_pinned(v);
-		self.used += wrapper.size();
+		wrapper.size.set(wrapper.compute_size());
+		self.used += wrapper.size.get();
 		wrapper.unroot_children(); // Unroot children
 		self.objects.push(wrapper);
 		self.objects.last_mut().unwrap()
@@ -241,6 +255,11 @@ impl GCHeap {
 	pub fn make_value<T: GC>(&mut self, v: T) -> Value {
 		Value::from_pointer(self.add(v), true) // Root new object
 	}
+	/// Like [`GCHeap::make_value`], but fails instead if the object does not fit under the memory limit.
+	pub fn try_make_value<T: GC>(&mut self, v: T) -> Result<Value, HissyError> {
+		self.reserve(mem::size_of::<GCWrapper_<T>>() + v.heap_size())?;
+		Ok(self.make_value(v))
+	}
 	
 	/// Delete dead objects from heap.
 	/// 
@@ -257,21 +276,76 @@ impl GCHeap {
 		self.used = 0;
 		for wrapper in self.objects.iter_mut() {
 			wrapper.reset();
-			self.used += wrapper.size();
+			wrapper.size.set(wrapper.compute_size());
+			self.used += wrapper.size.get();
 		}
 	}
 	
 	/// Calls collect() if the used memory is past a threshold.
 	/// 
 	/// The threshold is set to some initial value, and will be set to double
-	/// the current usage at the end of any collection initiated by this function.
-	pub fn step(&mut self) {
+	/// the current usage at the end of any collection initiated by this function,
+	/// without going past the memory limit.
+	/// 
+	/// Fails if the memory limit is still exceeded after collecting.
+	pub fn step(&mut self) -> Result<(), HissyError> {
 		if self.used >= self.threshold {
 			self.collect();
 			self.threshold = self.used * 2;
+			if let Some(limit) = self.limit {
+				self.threshold = self.threshold.min(limit);
+				if self.used > limit {
+					return Err(out_of_memory(self.used, limit));
+				}
+			}
+		}
+		Ok(())
+	}
+	
+	/// Sets a hard cap on the memory used by the heap in bytes, or removes it with `None`.
+	/// 
+	/// Reaching the cap triggers a full collection, and [`GCHeap::step`] fails if the cap is
+	/// still exceeded afterwards, which the VM reports as an execution error.
+	/// Allocations made in the middle of an instruction are checked with [`GCHeap::reserve`],
+	/// [`GCHeap::try_make_value`] and [`GCHeap::update_size`].
+	pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+		self.limit = limit;
+		if let Some(limit) = limit {
+			self.threshold = self.threshold.min(limit);
 		}
 	}
 	
+	pub fn memory_limit(&self) -> Option<usize> {
+		self.limit
+	}
+	
+	/// Checks that `extra` more bytes fit under the memory limit, collecting first if they do not.
+	/// 
+	/// Natives building a large result outside of the heap should call this as it grows.
+	pub fn reserve(&mut self, extra: usize) -> Result<(), HissyError> {
+		if let Some(limit) = self.limit {
+			if self.used.saturating_add(extra) > limit {
+				self.collect();
+				if self.used.saturating_add(extra) > limit {
+					return Err(out_of_memory(self.used.saturating_add(extra), limit));
+				}
+			}
+		}
+		Ok(())
+	}
+	
+	/// Updates the memory accounted for an object which grew or shrank in place, like a list.
+	/// 
+	/// Sizes are also recomputed by every collection, but this lets the threshold and memory limit react sooner.
+	/// Fails if the object grew past the memory limit.
+	pub fn update_size<T: GC>(&mut self, obj: &GCRef<T>) -> Result<(), HissyError> {
+		let wrapper = obj.wrapper();
+		let size = wrapper.compute_size();
+		let old_size = wrapper.size.replace(size);
+		self.used = self.used - old_size + size;
+		if size > old_size { self.reserve(0) } else { Ok(()) }
+	}
+	
 	/// Inspect current heap contents. Prints to standard output.
 	pub fn inspect(&self) {
 		println!("[GC inspect] ({}B used, collect at {}B)", self.used, self.threshold);
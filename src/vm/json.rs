@@ -0,0 +1,365 @@
+
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use num_bigint::BigInt;
+
+use crate::{HissyError, ErrorType};
+use super::gc::{GCHeap, GCRef, GCWrapper};
+use super::value::{Value, NIL};
+use super::object::{List, Map};
+
+fn error(s: String) -> HissyError {
+	HissyError::new(ErrorType::Execution, s, 0)
+}
+
+
+struct Parser<'a, 'b> {
+	text: &'a str,
+	it: Peekable<CharIndices<'a>>,
+	heap: &'b mut GCHeap,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+	fn pos(&mut self) -> usize {
+		let len = self.text.len();
+		self.it.peek().map_or(len, |(i, _)| *i)
+	}
+	
+	fn fail<T>(&mut self, msg: &str) -> Result<T, HissyError> {
+		let pos = self.pos();
+		Err(error(format!("Invalid JSON at offset {}: {}", pos, msg)))
+	}
+	
+	fn skip_whitespace(&mut self) {
+		while let Some((_, ' ')) | Some((_, '\t')) | Some((_, '\n')) | Some((_, '\r')) = self.it.peek() {
+			self.it.next();
+		}
+	}
+	
+	fn expect(&mut self, c: char) -> Result<(), HissyError> {
+		self.skip_whitespace();
+		match self.it.peek() {
+			Some((_, c2)) if *c2 == c => {
+				self.it.next();
+				Ok(())
+			},
+			_ => self.fail(&format!("expected '{}'", c)),
+		}
+	}
+	
+	fn parse_keyword(&mut self, keyword: &str, val: Value) -> Result<Value, HissyError> {
+		for c in keyword.chars() {
+			match self.it.next() {
+				Some((_, c2)) if c2 == c => {},
+				_ => return self.fail(&format!("expected '{}'", keyword)),
+			}
+		}
+		Ok(val)
+	}
+	
+	fn parse_value(&mut self) -> Result<Value, HissyError> {
+		self.skip_whitespace();
+		match self.it.peek().map(|(_, c)| *c) {
+			Some('n') => self.parse_keyword("null", NIL),
+			Some('t') => self.parse_keyword("true", Value::from(true)),
+			Some('f') => self.parse_keyword("false", Value::from(false)),
+			Some('"') => {
+				let s = self.parse_string()?;
+				self.heap.try_make_value(s)
+			},
+			Some('[') => self.parse_array(),
+			Some('{') => self.parse_object(),
+			Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+			Some(_) => self.fail("unexpected character"),
+			None => self.fail("unexpected end of text"),
+		}
+	}
+	
+	fn parse_number(&mut self) -> Result<Value, HissyError> {
+		let start = self.pos();
+		let mut is_int = true;
+		while let Some((_, c)) = self.it.peek() {
+			match c {
+				'0'..='9' | '-' => {},
+				'.' | 'e' | 'E' | '+' => is_int = false,
+				_ => break,
+			}
+			self.it.next();
+		}
+		let end = self.pos();
+		let s = &self.text[start..end];
+		let digits = s.strip_prefix('-').unwrap_or(s);
+		let leading_zero = digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit());
+		if !leading_zero {
+			if is_int {
+				if let Ok(b) = s.parse::<BigInt>() {
+					return Ok(Value::from_bigint(b, self.heap));
+				}
+			} else if let Ok(r) = s.parse::<f64>() {
+				return Ok(Value::from(r));
+			}
+		}
+		Err(error(format!("Invalid JSON at offset {}: invalid number {}", start, s)))
+	}
+	
+	fn parse_hex4(&mut self) -> Result<u32, HissyError> {
+		let mut code = 0;
+		for _ in 0..4 {
+			match self.it.next().and_then(|(_, c)| c.to_digit(16)) {
+				Some(d) => code = code * 16 + d,
+				None => return self.fail("invalid unicode escape"),
+			}
+		}
+		Ok(code)
+	}
+	
+	fn parse_string(&mut self) -> Result<String, HissyError> {
+		self.expect('"')?;
+		let mut s = String::new();
+		loop {
+			match self.it.next() {
+				Some((_, '"')) => return Ok(s),
+				Some((_, '\\')) => {
+					let c = match self.it.next() {
+						Some((_, '"')) => '"',
+						Some((_, '\\')) => '\\',
+						Some((_, '/')) => '/',
+						Some((_, 'b')) => '\u{8}',
+						Some((_, 'f')) => '\u{c}',
+						Some((_, 'n')) => '\n',
+						Some((_, 'r')) => '\r',
+						Some((_, 't')) => '\t',
+						Some((_, 'u')) => {
+							let mut code = self.parse_hex4()?;
+							if (0xD800..0xDC00).contains(&code) { // High surrogate, followed by a low one
+								self.parse_keyword("\\u", NIL)?;
+								let low = self.parse_hex4()?;
+								if !(0xDC00..0xE000).contains(&low) {
+									return self.fail("invalid surrogate pair");
+								}
+								code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+							}
+							match std::char::from_u32(code) {
+								Some(c) => c,
+								None => return self.fail("invalid unicode escape"),
+							}
+						},
+						_ => return self.fail("invalid escape sequence"),
+					};
+					s.push(c);
+				},
+				Some((_, c)) if c < ' ' => return self.fail("unescaped control character in string"),
+				Some((_, c)) => s.push(c),
+				None => return self.fail("unterminated string"),
+			}
+		}
+	}
+	
+	// Calls parse_item for each comma-separated item until the closing character
+	fn parse_items(&mut self, close: char, mut parse_item: impl FnMut(&mut Self) -> Result<(), HissyError>) -> Result<(), HissyError> {
+		self.skip_whitespace();
+		if let Some((_, c)) = self.it.peek() {
+			if *c == close {
+				self.it.next();
+				return Ok(());
+			}
+		}
+		loop {
+			parse_item(self)?;
+			self.skip_whitespace();
+			match self.it.next() {
+				Some((_, ',')) => {},
+				Some((_, c)) if c == close => return Ok(()),
+				_ => return self.fail(&format!("expected ',' or '{}'", close)),
+			}
+		}
+	}
+	
+	fn parse_array(&mut self) -> Result<Value, HissyError> {
+		self.expect('[')?;
+		let list = self.heap.make_ref(List::new());
+		self.parse_items(']', |p| {
+			list.extend(&[ p.parse_value()? ]);
+			p.heap.update_size(&list)
+		})?;
+		Ok(Value::from(list))
+	}
+	
+	fn parse_object(&mut self) -> Result<Value, HissyError> {
+		self.expect('{')?;
+		let map = Map::new();
+		self.parse_items('}', |p| {
+			p.skip_whitespace();
+			let key = p.parse_string()?;
+			p.expect(':')?;
+			map.set(key, p.parse_value()?);
+			Ok(())
+		})?;
+		self.heap.try_make_value(map)
+	}
+}
+
+/// Parses a JSON text into Hissy values: objects become `Map`s, and arrays `List`s.
+pub fn parse(text: &str, heap: &mut GCHeap) -> Result<Value, HissyError> {
+	let mut parser = Parser { text, it: text.char_indices().peekable(), heap };
+	let val = parser.parse_value()?;
+	parser.skip_whitespace();
+	if parser.it.peek().is_some() {
+		return parser.fail("unexpected text after value");
+	}
+	Ok(val)
+}
+
+
+struct Stringifier<'a> {
+	heap: &'a mut GCHeap,
+	indent: usize,
+	out: String,
+	visiting: Vec<*const GCWrapper>, // Objects being encoded, to detect cycles
+}
+
+impl<'a> Stringifier<'a> {
+	fn write_string(&mut self, s: &str) {
+		self.out.push('"');
+		for c in s.chars() {
+			match c {
+				'"' => self.out.push_str("\\\""),
+				'\\' => self.out.push_str("\\\\"),
+				'\n' => self.out.push_str("\\n"),
+				'\r' => self.out.push_str("\\r"),
+				'\t' => self.out.push_str("\\t"),
+				c if c < ' ' => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+				c => self.out.push(c),
+			}
+		}
+		self.out.push('"');
+	}
+	
+	fn newline(&mut self, depth: usize) {
+		if self.indent > 0 {
+			self.out.push('\n');
+			self.out.push_str(&" ".repeat(self.indent * depth));
+		}
+	}
+	
+	// Writes items between open and close, one per line if indenting
+	fn write_items<T>(&mut self, items: Vec<T>, open: char, close: char, depth: usize,
+			mut write_item: impl FnMut(&mut Self, T) -> Result<(), HissyError>) -> Result<(), HissyError> {
+		self.out.push(open);
+		let empty = items.is_empty();
+		for (i, item) in items.into_iter().enumerate() {
+			if i > 0 {
+				self.out.push(',');
+			}
+			self.newline(depth + 1);
+			write_item(self, item)?;
+			self.heap.reserve(self.out.capacity())?;
+		}
+		if !empty {
+			self.newline(depth);
+		}
+		self.out.push(close);
+		Ok(())
+	}
+	
+	fn write_value(&mut self, val: &Value, depth: usize) -> Result<(), HissyError> {
+		if val.is_nil() {
+			self.out.push_str("null");
+		} else if let Ok(b) = bool::try_from(val) {
+			self.out.push_str(if b { "true" } else { "false" });
+		} else if val.is_int() {
+			self.out.push_str(&val.repr());
+		} else if let Ok(r) = f64::try_from(val) {
+			if !r.is_finite() {
+				return Err(error(format!("Cannot encode {} as JSON", val.repr())));
+			}
+			self.out.push_str(&val.repr());
+		} else if let Ok(s) = GCRef::<String>::try_from(val.clone()) {
+			self.write_string(&s);
+		} else {
+			let ptr = val.get_pointer().unwrap() as *const GCWrapper;
+			if self.visiting.contains(&ptr) {
+				return Err(error(String::from("Cannot encode a cyclic structure as JSON")));
+			}
+			self.visiting.push(ptr);
+			if let Ok(list) = GCRef::<List>::try_from(val.clone()) {
+				self.write_items(list.get_copy(), '[', ']', depth, |s, item| s.write_value(&item, depth + 1))?;
+			} else if let Ok(map) = GCRef::<Map>::try_from(val.clone()) {
+				let sep = if self.indent > 0 { ": " } else { ":" };
+				self.write_items(map.get_entries(), '{', '}', depth, |s, (key, item)| {
+					s.write_string(&key);
+					s.out.push_str(sep);
+					s.write_value(&item, depth + 1)
+				})?;
+			} else {
+				return Err(error(format!("Cannot encode {} as JSON", val.repr())));
+			}
+			self.visiting.pop();
+		}
+		Ok(())
+	}
+}
+
+/// Encodes a Hissy value as JSON. If `indent` is not zero, the output is pretty-printed
+/// with that many spaces per level.
+/// 
+/// Fails if the output grows past the memory limit of the heap.
+pub fn stringify(val: &Value, indent: usize, heap: &mut GCHeap) -> Result<String, HissyError> {
+	let mut stringifier = Stringifier { heap, indent, out: String::new(), visiting: vec![] };
+	stringifier.write_value(val, 0)?;
+	Ok(stringifier.out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::tests::eval;
+	
+	#[test]
+	fn test_json() {
+		assert_eq!(eval(&format!("return json.parse({:?})\n", r#" [1, -2.5, 1e2, 123456789012345678901234, true, false, null, "\u00e9\n"] "#)).unwrap(),
+			"[1, -2.5, 100.0, 123456789012345678901234, true, false, nil, \"\u{e9}\\n\"]");
+		let bad = [
+			("[1,]", "Invalid JSON at offset 3: unexpected character"),
+			("[01]", "Invalid JSON at offset 1: invalid number 01"),
+			("[1] x", "Invalid JSON at offset 4: unexpected text after value"),
+			("{\"a\" 1}", "Invalid JSON at offset 5: expected ':'"),
+			("", "Invalid JSON at offset 0: unexpected end of text"),
+		];
+		for (text, msg) in bad.iter() {
+			assert_eq!(eval(&format!("return json.parse({:?})\n", text)).unwrap_err().1, *msg, "{}", text);
+		}
+		
+		let code = "return json.stringify(json.parse(\"{\\\"a\\\": [1, {}], \\\"b\\\": []}\"), 2)\n";
+		assert_eq!(eval(code).unwrap(), format!("{:?}", "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": []\n}"));
+		assert_eq!(eval("return json.stringify([1.5, nil, \"\\t\"], 0)\n").unwrap(), format!("{:?}", r#"[1.5,null,"\t"]"#));
+		
+		let errors = [
+			("let l = [json.parse(\"0\")]\nl.add(l)\nreturn json.stringify(l, 0)\n", "Cannot encode a cyclic structure as JSON"),
+			("return json.stringify([1.0 / 0.0], 0)\n", "Cannot encode inf as JSON"),
+			("return json.stringify(1, 0 - 1)\n", "Expected a non-negative indent, got -1"),
+		];
+		for (code, msg) in errors.iter() {
+			assert_eq!(eval(code).unwrap_err().1, *msg, "{}", code);
+		}
+	}
+	
+	const DOC: &str = "let m = map(json.parse(\"{\\\"name\\\": \\\"hissy\\\", \\\"tags\\\": [1, null]}\"))\n";
+	
+	#[test]
+	fn test_json_map() {
+		assert_eq!(eval(&[DOC, "return m.get(\"name\")\n"].concat()).unwrap(), "\"hissy\"");
+		assert_eq!(eval(&[DOC, "return [m.size(), m.has(\"tags\"), m.has(\"x\"), m.get(\"x\")]\n"].concat()).unwrap(),
+			"[2, true, false, nil]");
+		assert_eq!(eval(&[DOC, "return m.keys().join(\",\")\n"].concat()).unwrap(), "\"name,tags\"");
+		
+		// Parsed arrays can be used as lists, whose elements are parsed values
+		let code = "let l = list(json.parse(\"[1, [2, {\\\"a\\\": [3]}], []]\"))\nlet inner = list(l[1])\nlet a = list(map(inner[1]).get(\"a\"))\na.add(4)\nreturn [l.size(), inner[0], a, json.stringify(l, 0)]\n";
+		assert_eq!(eval(code).unwrap(), format!("[3, 2, [3, 4], {:?}]", r#"[1,[2,{"a":[3,4]}],[]]"#));
+		
+		// Objects keep their key order through a round trip
+		let text = r#"{"a":[1,{"b":null,"c":"d\"e"}],"f":{},"g":2.5}"#;
+		assert_eq!(eval(&format!("return json.stringify(json.parse({:?}), 0)\n", text)).unwrap(), format!("{:?}", text));
+	}
+}
@@ -15,13 +15,20 @@
 //! - `Nop`: No effect
 //! - `Cpy(rc, r)`: Copies `rc` into `r`
 //! - `GetUp(u, r)`, `SetUp(u, rc)`: Gets or sets an upvalue with a register
-//! - `Neg/Not(rc, r)`: Computes `-rc`/`not rc` and storing the result in `r`
-//! - `Or/And/Eq/Neq/Lth/Leq/Gth/Geq/Add/Sub/Mul/Div/Mod/Pow(rc1, rc2, r)`:
+//! - `Neg/Not/BitNot(rc, r)`: Computes `-rc`/`not rc`/`~rc` and storing the result in `r`
+//! - `Or/And/Eq/Neq/Lth/Leq/Gth/Geq/Add/Sub/Mul/Div/Mod/Pow/IntDiv/BitAnd/BitOr/BitXor/Shl/Shr(rc1, rc2, r)`:
 //!    
 //!    Applies the corresponding binary operation to `rc1` and `rc2`, storing the result in `r`
 //! - `Func(c, r)`: Creates a closure from the chunk with index `c`, storing the result in `r`
 //! - `Call(r1, r2, r3)`: Calls the function in `r1`, using arguments starting at `r2`, storing the result in `r3`
 //! - `Ret(rc)`: Returns `rc` from the current function
+//! - `Yield(rc)`: Suspends the current coroutine, producing `rc` as its next value
+//! - `Iter(rc, r)`: Gets an iterator over the list, iterator or coroutine in `rc`, storing it in `r`
+//! - `IterNext(a, r1, r2)`: Stores the next value of the iterator in `r1` into `r2`, or jumps to `a` if it is exhausted
+//! - `Range/RangeIncl(rc1, rc2, r)`: Creates an iterator over the integers from `rc1` to `rc2` (excluded/included), storing it in `r`
+//! - `Slice(rc1, rc2, rc3, r)`: Copies the part of the list or string in `rc1` from index `rc2` to `rc3` into `r`
+//!   (negative indices count from the end, and `nil` bounds stand for the start/end)
+//! - `GetMember(rc, i, r)`: Gets the member with one-byte index `i` of the module in `rc`, storing it in `r`
 //! - `Jmp(a)`: Unconditional jump to `a`
 //! - `Jit/Jif(a, rc)`: Jumps to `a` if `rc` is true/false (panics if not a boolean)
 //! 
@@ -32,29 +39,52 @@ pub mod gc;
 pub mod value;
 mod op;
 mod object;
+mod adaptors;
+mod random;
+mod console;
+mod clock;
+mod environment;
+mod convert;
+mod json;
 pub(crate) mod prelude;
 
+pub use object::{Coroutine, CoroutineStatus, NativeFunction};
+pub use console::OutputBuffer;
+pub use clock::{Clock, SystemClock, FakeClock};
+pub use environment::Environment;
+pub use convert::{HissyType, FromValue, IntoValue, IntoResult, HostFunction};
+
 
 use std::collections::HashMap;
 use num_enum::TryFromPrimitive;
 use std::ops::Deref;
 use std::convert::TryFrom;
-use std::{slice, iter};
+use std::{slice, iter, mem};
+use std::io::{self, Write, BufRead, BufReader};
 
-use crate::{HissyError, ErrorType};
+use crate::{HissyError, ErrorType, Frame};
 use crate::serial::*;
 use crate::compiler::chunk::{Chunk, Program};
 
-use gc::{GCHeap, GCRef};
+use gc::{GCHeap, GCRef, Traceable};
 use value::{Value, NIL};
 use object::*;
+use random::Rng;
 
 
 pub(crate) const MAX_REGISTERS: u8 = 128;
 
+// Number of instructions between checks of the time limit, since reading the clock is slow
+const DEADLINE_CHECK_INTERVAL: u32 = 256;
+
+// Default limits of the call stack, so that runaway recursion fails before exhausting the host's memory
+const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+const DEFAULT_MAX_STACK_SIZE: usize = 1 << 20;
+const DEFAULT_MAX_NATIVE_DEPTH: u32 = 100;
+
 
 fn error(s: String) -> HissyError {
-	HissyError(ErrorType::Execution, s, 0)
+	HissyError::new(ErrorType::Execution, s, 0)
 }
 fn error_str(s: &str) -> HissyError {
 	error(String::from(s))
@@ -65,13 +95,15 @@ fn error_str(s: &str) -> HissyError {
 pub(crate) enum InstrType {
 	Nop,
 	Cpy, GetUp, SetUp, CloseUp, GetExt,
-	Neg, Add, Sub, Mul, Div, Mod, Pow,
+	Neg, Add, Sub, Mul, Div, Mod, Pow, IntDiv,
+	BitNot, BitAnd, BitOr, BitXor, Shl, Shr,
 	Not, Or, And,
 	Eq, Neq, Lth, Leq, Gth, Geq,
-	Func, Call, Ret,
-	ListNew, ListExtend, ListGet, ListSet,
-	MakeMethod, CallMethod,
-	Jmp, Jit, Jif, Jin,
+	Func, Call, Ret, Yield,
+	Iter, IterNext, Range, RangeIncl,
+	ListNew, ListExtend, ListGet, ListSet, Slice,
+	MakeMethod, CallMethod, GetMember,
+	Jmp, Jit, Jif,
 }
 
 
@@ -88,6 +120,93 @@ struct ExecRecord {
 }
 
 
+/// The register stack and call stack of a suspended line of execution.
+pub(super) struct Fiber {
+	regs: Registers,
+	calls: Vec<ExecRecord>,
+	pos: usize,
+	resume_reg: Option<u8>, // Where the value passed to the next resume goes
+}
+
+impl Fiber {
+	// Creates a fiber which will start by calling func with the given arguments
+	fn new(program: &Program, func: GCRef<Closure>, args: &[Value]) -> Fiber {
+		let mut regs = Registers::new();
+		regs.allocate(program.chunks[usize::from(func.chunk_id)].nb_registers);
+		for (reg, arg) in regs.registers.iter_mut().zip(args) {
+			*reg = arg.clone();
+		}
+		let reg_win = (0, regs.registers.len());
+		Fiber {
+			regs,
+			calls: vec![ExecRecord {
+				closure: func,
+				upvalues: HashMap::new(),
+				return_params: None,
+				reg_win,
+			}],
+			pos: 0,
+			resume_reg: None,
+		}
+	}
+	
+	// Turns back all references held by the fiber into roots, when taking it out of the heap
+	pub fn root(&mut self) {
+		for val in self.regs.registers.iter_mut() {
+			*val = val.clone();
+		}
+		for call in self.calls.iter_mut() {
+			call.closure = call.closure.clone();
+			for upv in call.upvalues.values_mut() {
+				*upv = upv.clone();
+			}
+		}
+	}
+}
+
+impl Traceable for Fiber {
+	fn touch(&self, initial: bool) {
+		self.regs.registers.touch(initial);
+		for call in &self.calls {
+			call.closure.touch(initial);
+			for upv in call.upvalues.values() {
+				upv.touch(initial);
+			}
+		}
+	}
+	
+	fn heap_size(&self) -> usize {
+		self.regs.registers.heap_size() + self.calls.capacity() * mem::size_of::<ExecRecord>()
+	}
+}
+
+/// The final state of the main chunk's registers, kept when it returns so the host can look up its bindings.
+struct MainBindings {
+	regs: Vec<Value>,
+	upvalues: HashMap<u8, GCRef<Upvalue>>, // Bindings captured by closures, which may still change
+}
+
+/// Where the values produced by a resumed coroutine go.
+#[derive(Clone, Copy)]
+enum ResumeMode {
+	/// Back to the host
+	Host,
+	/// Into a register
+	Value(u8),
+	/// Into a register, for a `for` loop which jumps to an address when the coroutine returns
+	Iterate(u8, usize),
+	/// Into a register, for a call to `next` which fails when the coroutine returns
+	Next(u8),
+}
+
+/// A fiber waiting for a coroutine to yield or return.
+struct Resumer {
+	fiber: Fiber,
+	coroutine: GCRef<Coroutine>,
+	mode: ResumeMode,
+}
+
+
 enum ValueRef<'a> {
 	Reg(&'a Value),
 	Temp(Value),
@@ -115,10 +234,6 @@ impl Registers {
 		Registers { registers: vec![], window_start: 0 }
 	}
 	
-	pub fn shift_window(&mut self, n: u16) {
-		self.window_start += usize::from(n);
-	}
-	
 	pub fn reset_window(&mut self, start: usize, end: usize) {
 		self.registers.splice(self.window_start.., iter::repeat(NIL).take(end.saturating_sub(self.window_start)));
 		// Note: self.registers.resize(end, NIL) is more economical, but less precise
@@ -129,10 +244,6 @@ impl Registers {
 		self.registers.resize(self.registers.len() + usize::from(n), NIL);
 	}
 	
-	pub fn free_all(&mut self) {
-		self.registers.clear();
-	}
-	
 	pub fn reg_or_cst(&self, chunk: &Chunk, heap: &mut GCHeap, reg: u8) -> Result<ValueRef, HissyError> {
 		if reg < MAX_REGISTERS {
 			let reg2 = self.window_start + (reg as usize);
@@ -176,6 +287,24 @@ fn read_rel_add<'a>(it: &mut slice::Iter<'a, u8>, code: &'a [u8]) -> Result<usiz
 	usize::try_from(pos + rel_add).map_err(|_| error_str("Jumped back too far"))
 }
 
+// The name of the function is only known with debug info
+fn stack_overflow(chunk: &Chunk) -> HissyError {
+	let name = &chunk.debug_info.name;
+	if name.is_empty() { error_str("Stack overflow") } else { error(format!("Stack overflow in function {}", name)) }
+}
+
+// Finds the line of the instruction at pos in a chunk, or 0 without debug info
+fn chunk_line(program: &Program, chunk: &Chunk, pos: usize) -> u16 {
+	if !program.debug_info {
+		return 0;
+	}
+	let line_numbers = &chunk.debug_info.line_numbers;
+	let line_idx = line_numbers.iter().position(|(pos2, _)| pos < usize::from(*pos2))
+		.unwrap_or(line_numbers.len()) - 1;
+	line_numbers.get(line_idx)
+		.expect("Could not get line number of instruction").1
+}
+
 fn iter_from(code: &[u8], pos: usize) -> slice::Iter<u8> {
 	code.get(pos..).expect("Jumped forward too far").iter()
 }
@@ -187,7 +316,26 @@ struct VMState<'a> {
 	chunk: &'a Chunk,
 	it: slice::Iter<'a, u8>,
 	calls: Vec<ExecRecord>,
+	resumers: Vec<Resumer>,
 	external: Vec<Value>,
+	host_result: Option<Value>,
+	resumers_base: usize, // Resumers below this belong to an enclosing native call or the host
+	rng: Rng,
+	clock: Box<dyn Clock>,
+	output: Box<dyn Write>,
+	input: Box<dyn BufRead>,
+	fs_allowed: bool,
+	fs_error: String, // Message of the last failed `fs` operation
+	exit_code: Option<i32>, // Set by `exit`, whose error then unwinds the script up to the host
+	main_bindings: Option<MainBindings>,
+	fuel: Option<u64>, // Instructions left before pausing, if limited
+	deadline: Option<f64>, // Time of the clock at which to pause, if limited
+	steps: u32, // Instructions executed, modulo DEADLINE_CHECK_INTERVAL
+	nested_runs: u32, // Runs started by native code or host calls, which cannot be paused
+	paused: bool,
+	max_call_depth: usize,
+	max_stack_size: usize, // In registers
+	max_native_depth: u32, // Of nested runs, since they recurse on the Rust stack
 }
 
 impl<'a> VMState<'a> {
@@ -198,7 +346,26 @@ impl<'a> VMState<'a> {
 			chunk: program.chunks.get(0).expect("Program contains no chunks"),
 			it: [].iter(),
 			calls: vec![],
+			resumers: vec![],
 			external: vec![],
+			host_result: None,
+			resumers_base: 0,
+			rng: Rng::from_time(),
+			clock: Box::new(SystemClock::new()),
+			output: Box::new(io::stdout()),
+			input: Box::new(BufReader::new(io::stdin())),
+			fs_allowed: false,
+			fs_error: String::new(),
+			exit_code: None,
+			main_bindings: None,
+			fuel: None,
+			deadline: None,
+			steps: 0,
+			nested_runs: 0,
+			paused: false,
+			max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+			max_stack_size: DEFAULT_MAX_STACK_SIZE,
+			max_native_depth: DEFAULT_MAX_NATIVE_DEPTH,
 		};
 		vm.regs.allocate(vm.chunk.nb_registers);
 		vm
@@ -208,14 +375,19 @@ impl<'a> VMState<'a> {
 		usize::try_from(self.chunk.code.len() - self.it.len()).unwrap()
 	}
 	
-	pub fn call(&mut self, program: &'a Program, func: GCRef<Closure>, args_start: u8, ret_reg: Option<u8>) {
+	// Calls a closure, with a register window starting at the absolute index args_start
+	pub fn call(&mut self, program: &'a Program, func: GCRef<Closure>, args_start: usize, ret_reg: Option<u8>) -> Result<(), HissyError> {
+		let chunk = &program.chunks[usize::from(func.chunk_id)];
+		if self.calls.len() >= self.max_call_depth || args_start + usize::from(chunk.nb_registers) > self.max_stack_size {
+			return Err(stack_overflow(chunk));
+		}
 		let ret_add = self.pos();
 		
 		self.chunk_id = usize::from(func.chunk_id);
-		self.chunk = &program.chunks[self.chunk_id];
+		self.chunk = chunk;
 		self.it = self.chunk.code.iter();
 		
-		self.regs.shift_window(u16::from(args_start));
+		self.regs.window_start = args_start;
 		self.regs.registers.resize(self.regs.window_start + usize::from(self.chunk.nb_registers), NIL);
 		
 		self.calls.push(ExecRecord {
@@ -227,314 +399,1397 @@ impl<'a> VMState<'a> {
 			}),
 			reg_win: (self.regs.window_start, self.regs.registers.len()),
 		});
+		Ok(())
 	}
 	
-	fn call_native(&mut self, heap: &mut GCHeap, func: Value, this: Option<Value>, args_start: u8, args_cnt: u8, rout: u8) -> Result<bool, HissyError> {
+	#[allow(clippy::too_many_arguments)]
+	fn call_native(&mut self, program: &'a Program, heap: &mut GCHeap, func: Value, this: Option<Value>, args_start: u8, args_cnt: u8, rout: u8) -> Result<bool, HissyError> {
 		let mut args = self.regs.reg_range(args_start, args_cnt).to_vec();
 		if let Some(this) = this { args.insert(0, this); }
-		if let Ok(func) = GCRef::<NativeFunction>::try_from(func) {
-			let res = func.call(heap, args.to_vec())?;
+		if let Ok(func) = GCRef::<NativeFunction>::try_from(func.clone()) {
+			let res = func.call(&mut VMHandle { heap, program, state: self }, args)?;
 			*self.regs.mut_reg(rout) = res;
 			Ok(true)
+		} else if let Ok(intrinsic) = GCRef::<Intrinsic>::try_from(func) {
+			self.call_intrinsic(program, heap, *intrinsic, args, Some(rout))?;
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	}
+	
+	// Calls an intrinsic, putting its result into rout
+	// When called from native code, rout is None and the result is returned instead
+	fn call_intrinsic(&mut self, program: &'a Program, heap: &mut GCHeap, intrinsic: Intrinsic, args: Vec<Value>, rout: Option<u8>) -> Result<Option<Value>, HissyError> {
+		let res = match (intrinsic, rout) {
+			(Intrinsic::IterNext, Some(rout)) => {
+				if !self.iter_next(program, heap, args[0].clone(), rout, None)? {
+					return Err(error_str("Iterator is exhausted"));
+				}
+				None
+			},
+			(Intrinsic::IterNext, None) => {
+				let val = self.next_value(program, heap, args[0].clone())?;
+				Some(val.ok_or_else(|| error_str("Iterator is exhausted"))?)
+			},
+			(Intrinsic::MakeCoroutine, _) => {
+				let func = GCRef::<Closure>::try_from(args[0].clone())
+					.map_err(|_| error(format!("Cannot make a coroutine from {}", args[0].repr())))?;
+				let fiber = Fiber::new(program, func, &[]);
+				Some(heap.make_value(Coroutine::new(fiber, false)))
+			},
+			(Intrinsic::Resume, Some(rout)) => {
+				let co = GCRef::<Coroutine>::try_from(args[0].clone()).unwrap();
+				if !self.resume(program, co, &args[1..], ResumeMode::Value(rout))? {
+					return Err(error_str("Cannot resume dead coroutine"));
+				}
+				None
+			},
+			(Intrinsic::Resume, None) => {
+				let co = GCRef::<Coroutine>::try_from(args[0].clone()).unwrap();
+				let val = self.resume_from_host(program, heap, co, &args[1..])?;
+				Some(val.ok_or_else(|| error_str("Cannot resume dead coroutine"))?)
+			},
+			(Intrinsic::Suspend, Some(rout)) => {
+				let val = args.first().cloned().unwrap_or(NIL);
+				self.suspend(program, val, Some(rout))?;
+				None
+			},
+			(Intrinsic::Suspend, None) => return Err(error_str("Cannot yield across a native function call")),
+		};
+		if let (Some(rout), Some(val)) = (rout, &res) {
+			*self.regs.mut_reg(rout) = val.clone();
+			return Ok(None);
+		}
+		Ok(res)
+	}
+	
+	// Calls any callable value from native code, and returns its result
+	fn call_value(&mut self, program: &'a Program, heap: &mut GCHeap, func: Value, mut args: Vec<Value>) -> Result<Value, HissyError> {
+		if let Ok(method) = GCRef::<Method>::try_from(func.clone()) {
+			args.insert(0, method.this.clone());
+			self.call_value(program, heap, method.func.clone(), args)
+		} else if let Ok(native) = GCRef::<NativeFunction>::try_from(func.clone()) {
+			native.call(&mut VMHandle { heap, program, state: self }, args)
+		} else if let Ok(intrinsic) = GCRef::<Intrinsic>::try_from(func.clone()) {
+			Ok(self.call_intrinsic(program, heap, *intrinsic, args, None)?.unwrap())
+		} else if let Ok(func) = GCRef::<Closure>::try_from(func.clone()) {
+			if program.chunks[usize::from(func.chunk_id)].is_generator {
+				let fiber = Fiber::new(program, func, &args);
+				return Ok(heap.make_value(Coroutine::new(fiber, true)));
+			}
+			
+			if self.nested_runs >= self.max_native_depth {
+				return Err(stack_overflow(&program.chunks[usize::from(func.chunk_id)]));
+			}
+			
+			// Run the closure on top of the current register window, until it returns to us
+			let (chunk_id, chunk, it) = (self.chunk_id, self.chunk, self.it.clone());
+			let calls_len = self.calls.len();
+			let args_start = self.regs.registers.len();
+			self.regs.registers.extend(args);
+			if let Err(err) = self.call(program, func, args_start, None) {
+				self.regs.registers.truncate(args_start);
+				return Err(err);
+			}
+			
+			let resumers_base = mem::replace(&mut self.resumers_base, self.resumers.len());
+			let res = self.run_nested(program, heap);
+			self.resumers_base = resumers_base;
+			if res.is_err() {
+				self.abort_calls(calls_len);
+			}
+			
+			self.chunk_id = chunk_id;
+			self.chunk = chunk;
+			self.it = it;
+			res
+		} else {
+			Err(error(format!("Cannot call value {}", func.repr())))
+		}
+	}
+	
+	// Abandons the calls above calls_len after an error, closing their upvalues
+	fn abort_calls(&mut self, calls_len: usize) {
+		let aborted: Vec<ExecRecord> = self.calls.drain(calls_len..).collect();
+		for call in &aborted {
+			for (reg, upv) in &call.upvalues {
+				upv.set_inside(self.regs.registers[call.reg_win.0 + usize::from(*reg)].clone());
+			}
+		}
+		if let Some(first) = aborted.first() {
+			self.regs.registers.truncate(first.reg_win.0);
+			self.regs.window_start = self.calls.last().map_or(0, |call| call.reg_win.0);
+		}
+	}
+	
+	// Gets the next value of an iterator into rout, returning false if it is exhausted
+	// In a for loop, end_add is where to jump if a coroutine being iterated over returns
+	fn iter_next(&mut self, program: &'a Program, heap: &mut GCHeap, iter: Value, rout: u8, end_add: Option<usize>) -> Result<bool, HissyError> {
+		if let Ok(co) = GCRef::<Coroutine>::try_from(iter.clone()) {
+			let mode = end_add.map_or(ResumeMode::Next(rout), |end_add| ResumeMode::Iterate(rout, end_add));
+			return self.resume(program, co, &[], mode);
+		}
+		if let Some(val) = self.next_value(program, heap, iter)? {
+			*self.regs.mut_reg(rout) = val;
+			Ok(true)
 		} else {
 			Ok(false)
 		}
 	}
 	
-	pub fn ret(&mut self, program: &'a Program, ret_val: Value) -> Result<bool, HissyError> {
+	// Gets the next value of an iterator from native code, returning None if it is exhausted
+	fn next_value(&mut self, program: &'a Program, heap: &mut GCHeap, iter: Value) -> Result<Option<Value>, HissyError> {
+		if let Ok(co) = GCRef::<Coroutine>::try_from(iter.clone()) {
+			let val = self.resume_from_host(program, heap, co.clone(), &[])?;
+			// A coroutine which returns instead of yielding is exhausted
+			return Ok(val.filter(|_| co.status() != CoroutineStatus::Dead));
+		}
+		let iter = GCRef::<IteratorWrapper>::try_from(iter.clone())
+			.map_err(|_| error(format!("Cannot iterate over {}", iter.repr())))?;
+		iter.next(&mut VMHandle { heap, program, state: self })
+	}
+	
+	// Open upvalues refer to the active register stack, so they are closed while their fiber is not running
+	fn close_upvalues(&self) {
+		for call in &self.calls {
+			for (reg, upv) in &call.upvalues {
+				upv.set_inside(self.regs.registers[call.reg_win.0 + usize::from(*reg)].clone());
+			}
+		}
+	}
+	
+	fn reopen_upvalues(&mut self) {
+		for call in &self.calls {
+			for (reg, upv) in &call.upvalues {
+				let idx = call.reg_win.0 + usize::from(*reg);
+				self.regs.registers[idx] = upv.reopen(idx);
+			}
+		}
+	}
+	
+	// Makes fiber the active fiber, and returns the previously active one
+	fn switch_fiber(&mut self, program: &'a Program, fiber: Fiber) -> Fiber {
+		self.close_upvalues();
+		let Fiber { regs, calls, pos, .. } = fiber;
+		let prev = Fiber {
+			regs: mem::replace(&mut self.regs, regs),
+			calls: mem::replace(&mut self.calls, calls),
+			pos: self.pos(),
+			resume_reg: None,
+		};
+		if let Some(cur_call) = self.calls.last() {
+			self.chunk_id = usize::from(cur_call.closure.chunk_id);
+			self.chunk = &program.chunks[self.chunk_id];
+			self.it = iter_from(&self.chunk.code, pos);
+		}
+		self.reopen_upvalues();
+		prev
+	}
+	
+	// Resumes a coroutine, until it yields or returns a value according to mode
+	// Returns false if the coroutine has already returned
+	fn resume(&mut self, program: &'a Program, co: GCRef<Coroutine>, args: &[Value], mode: ResumeMode) -> Result<bool, HissyError> {
+		if let Some((mut fiber, started)) = co.take()? {
+			if !started {
+				for (reg, arg) in fiber.regs.registers.iter_mut().zip(args) {
+					*reg = arg.clone();
+				}
+			} else if let Some(reg) = fiber.resume_reg.take() {
+				*fiber.regs.mut_reg(reg) = args.first().cloned().unwrap_or(NIL);
+			}
+			let fiber = self.switch_fiber(program, fiber);
+			self.resumers.push(Resumer { fiber, coroutine: co, mode });
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	}
+	
+	// Resumes a coroutine from native code or the host, running it until it yields or returns
+	// Returns None if the coroutine has already returned
+	fn resume_from_host(&mut self, program: &'a Program, heap: &mut GCHeap, co: GCRef<Coroutine>, args: &[Value]) -> Result<Option<Value>, HissyError> {
+		if self.nested_runs >= self.max_native_depth {
+			return Err(match co.chunk_id() {
+				Some(chunk_id) => stack_overflow(&program.chunks[usize::from(chunk_id)]),
+				None => error_str("Stack overflow"),
+			});
+		}
+		let resumers_base = mem::replace(&mut self.resumers_base, self.resumers.len());
+		let res = match self.resume(program, co, args, ResumeMode::Host) {
+			Ok(true) => self.run_nested(program, heap).map(Some),
+			Ok(false) => Ok(None),
+			Err(err) => Err(err),
+		};
+		self.resumers_base = resumers_base;
+		res
+	}
+	
+	// Suspends the running coroutine; the value of its next resume will go into resume_reg
+	pub fn suspend(&mut self, program: &'a Program, val: Value, resume_reg: Option<u8>) -> Result<(), HissyError> {
+		if self.resumers.len() <= self.resumers_base {
+			return Err(error_str(if self.resumers.is_empty() {
+				"Cannot yield outside of a coroutine"
+			} else {
+				"Cannot yield across a native function call"
+			}));
+		}
+		let resumer = self.resumers.pop().unwrap();
+		let mut fiber = self.switch_fiber(program, resumer.fiber);
+		fiber.resume_reg = resume_reg;
+		resumer.coroutine.suspend(fiber);
+		self.deliver(program, resumer.mode, val, false)
+	}
+	
+	// Handles a value yielded or returned by a coroutine, once back in the resuming fiber
+	fn deliver(&mut self, program: &'a Program, mode: ResumeMode, val: Value, returned: bool) -> Result<(), HissyError> {
+		match mode {
+			ResumeMode::Host => self.host_result = Some(val),
+			ResumeMode::Iterate(_, end_add) if returned => self.it = iter_from(&self.chunk.code, end_add),
+			ResumeMode::Next(_) if returned => {
+				// The error belongs to the call to next, not to the instruction which ended the coroutine
+				let line = self.line_at(program, self.pos() - 1);
+				return Err(HissyError::new(ErrorType::Execution, String::from("Iterator is exhausted"), line));
+			},
+			ResumeMode::Value(reg) | ResumeMode::Iterate(reg, _) | ResumeMode::Next(reg) => *self.regs.mut_reg(reg) = val,
+		}
+		Ok(())
+	}
+	
+	// Finds the line of the instruction at pos in the current chunk, or 0 without debug info
+	fn line_at(&self, program: &Program, pos: usize) -> u16 {
+		chunk_line(program, self.chunk, pos)
+	}
+	
+	// Adds the calls made in the current run to the stack trace of an error which occurred at pos
+	// The calls of enclosing runs are added once the error reaches them
+	fn add_trace(&self, program: &Program, mut pos: usize, trace: &mut Vec<Frame>) {
+		let mut calls = &self.calls;
+		let mut resumers = self.resumers[self.resumers_base..].iter().rev();
+		loop {
+			for call in calls.iter().rev() {
+				let chunk_id = usize::from(call.closure.chunk_id);
+				let chunk = &program.chunks[chunk_id];
+				let name = Some(chunk.debug_info.name.clone()).filter(|name| !name.is_empty());
+				trace.push(Frame { chunk: chunk_id, name, line: chunk_line(program, chunk, pos) });
+				match &call.return_params {
+					Some(ret) => pos = ret.add - 1,
+					None => break, // Called from native code or the host, or started a coroutine
+				}
+			}
+			// Coroutines resumed from native code or the host start a run of their own
+			match resumers.next() {
+				Some(resumer) if !matches!(resumer.mode, ResumeMode::Host) => {
+					calls = &resumer.fiber.calls;
+					pos = resumer.fiber.pos - 1;
+				},
+				_ => return,
+			}
+		}
+	}
+	
+	// Kills the coroutines started by the current native call or host, after an error
+	fn unwind(&mut self, program: &'a Program) {
+		while self.resumers.len() > self.resumers_base {
+			let resumer = self.resumers.pop().unwrap();
+			self.switch_fiber(program, resumer.fiber);
+			resumer.coroutine.finish();
+		}
+	}
+	
+	pub fn ret(&mut self, program: &'a Program, ret_val: Value) -> Result<(), HissyError> {
 		let cur_call = self.calls.pop().unwrap();
+		// Returning from inside a block skips its CloseUp instructions
+		for (reg, upv) in &cur_call.upvalues {
+			upv.set_inside(self.regs.registers[cur_call.reg_win.0 + usize::from(*reg)].clone());
+		}
 		
-		if let Some(prev_call) = self.calls.last() {
-			assert!(cur_call.upvalues.is_empty(), "Upvalues where not all closed over");
-			
+		if let Some(ret) = cur_call.return_params {
+			let prev_call = self.calls.last().expect("No caller to return to");
 			self.regs.reset_window(prev_call.reg_win.0, prev_call.reg_win.1);
 			
 			self.chunk_id = prev_call.closure.chunk_id as usize;
 			self.chunk = &program.chunks[self.chunk_id];
-			let ret = cur_call.return_params.expect("No return address/register set");
 			self.it = iter_from(&self.chunk.code, ret.add);
 			*self.regs.mut_reg(ret.reg) = ret_val;
 			
-			Ok(false)
+		} else if self.calls.is_empty() && !self.resumers.is_empty() { // Return from coroutine
+			let resumer = self.resumers.pop().unwrap();
+			self.switch_fiber(program, resumer.fiber);
+			resumer.coroutine.finish();
+			self.deliver(program, resumer.mode, ret_val, true)?;
 			
-		} else { // Return from main chunk
-			self.chunk_id = 0;
-			self.chunk = &program.chunks[0];
-			self.it = [].iter();
-			
-			Ok(true)
+		} else { // Return from main chunk, or to native code
+			if cur_call.closure.chunk_id == 0 {
+				self.main_bindings = Some(MainBindings {
+					regs: self.regs.registers[cur_call.reg_win.0 .. cur_call.reg_win.1].to_vec(),
+					upvalues: cur_call.upvalues,
+				});
+			}
+			let prev_start = self.calls.last().map_or(0, |call| call.reg_win.0);
+			self.regs.reset_window(prev_start, cur_call.reg_win.0);
+			self.host_result = Some(ret_val);
 		}
+		
+		Ok(())
 	}
-}
-
-/// Runs a compiled Hissy program, using an existing GC heap.
-pub fn run_program(heap: &mut GCHeap, program: &Program) -> Result<(), HissyError> {
-	let mut vm = VMState::new(program);
-	
-	vm.external.extend(prelude::create(heap));
-	
 	
+	// Uses up one instruction of the budget, or returns why execution must stop if it is exhausted
+	fn use_budget(&mut self) -> Option<Outcome> {
+		if let Some(deadline) = self.deadline {
+			if self.steps == 0 && self.clock.now() >= deadline {
+				return Some(Outcome::OutOfTime);
+			}
+		}
+		if let Some(fuel) = &mut self.fuel {
+			if *fuel == 0 {
+				return Some(Outcome::OutOfFuel);
+			}
+			*fuel -= 1;
+		}
+		self.steps = (self.steps + 1) % DEADLINE_CHECK_INTERVAL;
+		None
+	}
 	
-	let main = heap.make_ref(Closure::new(0, vec![]));
-	vm.call(program, main, 0, None);
-	
-	macro_rules! bin_op {
-		($method:ident) => {{
-			let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
-			let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?;
-			let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?;
-			*vm.regs.mut_reg(c) = a.$method(&b)
-				.ok_or_else(|| error_str(concat!("Cannot ", stringify!($method), " these values")))?;
-		}};
+	// Runs bytecode for native code or a host call, which cannot be paused
+	fn run_nested(&mut self, program: &'a Program, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		self.nested_runs += 1;
+		let res = self.run(program, heap);
+		self.nested_runs -= 1;
+		match res? {
+			Outcome::Finished(val) => Ok(val),
+			_ => unreachable!("Nested runs cannot pause"),
+		}
 	}
 	
-	loop {
-		// println!("({}) {}@{}", vm.calls.len(), vm.chunk_id, vm.pos());
+	// Runs bytecode until a value is returned to the host, or the budget runs out
+	fn run(&mut self, program: &'a Program, heap: &mut GCHeap) -> Result<Outcome, HissyError> {
+		let vm = self;
 		
-		let instr_pos = vm.pos() as u16;
+		macro_rules! bin_op {
+			($method:ident) => {{
+				let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+				let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?;
+				let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?;
+				*vm.regs.mut_reg(c) = a.$method(&b)
+					.ok_or_else(|| error_str(concat!("Cannot ", stringify!($method), " these values")))?;
+			}};
+		}
+		
+		// Operations which can produce integers: they need the heap for big integers, and can fail
+		macro_rules! checked_bin_op {
+			($method:ident) => {{
+				let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+				let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?.clone();
+				let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?.clone();
+				*vm.regs.mut_reg(c) = a.$method(&b, heap)?;
+			}};
+		}
 		
-		let mut run_instr = || -> Result<bool, HissyError> {
-			if let Some(b) = vm.it.next() {
-				match InstrType::try_from(*b).unwrap() {
-					InstrType::Nop => (),
-					InstrType::Cpy => {
-						let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
-						let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
-						*vm.regs.mut_reg(rout) = rin.clone();
-					},
-					InstrType::Neg => {
-						let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
-						let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
-						*vm.regs.mut_reg(rout) = rin.neg().ok_or_else(|| error_str("Cannot negate value!"))?;
-					},
-					InstrType::Add => bin_op!(add),
-					InstrType::Sub => bin_op!(sub),
-					InstrType::Mul => bin_op!(mul),
-					InstrType::Div => bin_op!(div),
-					InstrType::Pow => bin_op!(pow),
-					InstrType::Mod => bin_op!(modulo),
-					InstrType::Not => {
-						let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
-						let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
-						*vm.regs.mut_reg(rout) = rin.not().ok_or_else(|| error_str("Cannot apply logical NOT to value"))?;
-					},
-					InstrType::Or => bin_op!(or),
-					InstrType::And => bin_op!(and),
-					InstrType::Eq => {
-						let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
-						let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?;
-						let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?;
-						*vm.regs.mut_reg(c) = Value::from(a.eq(&b));
-					},
-					InstrType::Neq => {
-						let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
-						let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?;
-						let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?;
-						*vm.regs.mut_reg(c) = Value::from(!a.eq(&b));
-					},
-					InstrType::Lth => bin_op!(lth),
-					InstrType::Leq => bin_op!(leq),
-					InstrType::Gth => bin_op!(gth),
-					InstrType::Geq => bin_op!(geq),
-					InstrType::Func => {
-						let chunk_id = read_u8(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						let chunk = program.chunks.get(chunk_id as usize)
-							.ok_or_else(|| error_str("Invalid chunk id"))?;
-						let cur_call = vm.calls.last_mut().unwrap();
-						let upvalues = chunk.upvalues.iter().copied().map(|reg| {
-							if reg < MAX_REGISTERS { // Upvalue points to register 
-								if let Some(upv) = cur_call.upvalues.get(&reg) {
-									upv.clone()
+		loop {
+			// println!("({}) {}@{}", vm.calls.len(), vm.chunk_id, vm.pos());
+			
+			let instr_pos = vm.pos();
+			
+			let mut exhausted = vm.use_budget();
+			if vm.nested_runs == 0 {
+				if let Some(outcome) = exhausted.take() {
+					vm.paused = true;
+					return Ok(outcome);
+				}
+			}
+			
+			let mut run_instr = || -> Result<bool, HissyError> {
+				if let Some(b) = vm.it.next() {
+					match InstrType::try_from(*b).unwrap() {
+						InstrType::Nop => (),
+						InstrType::Cpy => {
+							let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
+							*vm.regs.mut_reg(rout) = rin.clone();
+						},
+						InstrType::Neg => {
+							let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
+							let rin = rin.clone();
+							*vm.regs.mut_reg(rout) = rin.neg(heap)?;
+						},
+						InstrType::Add => checked_bin_op!(add),
+						InstrType::Sub => checked_bin_op!(sub),
+						InstrType::Mul => checked_bin_op!(mul),
+						InstrType::Div => bin_op!(div),
+						InstrType::Pow => checked_bin_op!(pow),
+						InstrType::Mod => checked_bin_op!(modulo),
+						InstrType::IntDiv => checked_bin_op!(int_div),
+						InstrType::BitNot => {
+							let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
+							let rin = rin.clone();
+							*vm.regs.mut_reg(rout) = rin.bit_not(heap)?;
+						},
+						InstrType::BitAnd => checked_bin_op!(bit_and),
+						InstrType::BitOr => checked_bin_op!(bit_or),
+						InstrType::BitXor => checked_bin_op!(bit_xor),
+						InstrType::Shl => checked_bin_op!(shl),
+						InstrType::Shr => checked_bin_op!(shr),
+						InstrType::Not => {
+							let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let rin = vm.regs.reg_or_cst(vm.chunk, heap, rin)?;
+							*vm.regs.mut_reg(rout) = rin.not().ok_or_else(|| error_str("Cannot apply logical NOT to value"))?;
+						},
+						InstrType::Or => bin_op!(or),
+						InstrType::And => bin_op!(and),
+						InstrType::Eq => {
+							let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?;
+							let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?;
+							*vm.regs.mut_reg(c) = Value::from(a.eq(&b));
+						},
+						InstrType::Neq => {
+							let (a, b, c) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let a = vm.regs.reg_or_cst(vm.chunk, heap, a)?;
+							let b = vm.regs.reg_or_cst(vm.chunk, heap, b)?;
+							*vm.regs.mut_reg(c) = Value::from(!a.eq(&b));
+						},
+						InstrType::Lth => bin_op!(lth),
+						InstrType::Leq => bin_op!(leq),
+						InstrType::Gth => bin_op!(gth),
+						InstrType::Geq => bin_op!(geq),
+						InstrType::Func => {
+							let chunk_id = read_u8(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							let chunk = program.chunks.get(chunk_id as usize)
+								.ok_or_else(|| error_str("Invalid chunk id"))?;
+							let cur_call = vm.calls.last_mut().unwrap();
+							let upvalues = chunk.upvalues.iter().copied().map(|reg| {
+								if reg < MAX_REGISTERS { // Upvalue points to register 
+									if let Some(upv) = cur_call.upvalues.get(&reg) {
+										upv.clone()
+									} else {
+										let idx = cur_call.reg_win.0 + (reg as usize);
+										let upv = heap.make_ref(Upvalue::new(idx));
+										cur_call.upvalues.insert(reg, upv.clone());
+										upv
+									}
+								} else { // Upvalue points to upvalue
+									cur_call.closure.upvalues[(reg - MAX_REGISTERS) as usize].clone()
+								}
+							}).collect();
+							*vm.regs.mut_reg(rout) = heap.make_value(Closure::new(chunk_id, upvalues));
+						},
+						InstrType::Call => {
+							let func = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?.clone();
+							let args_start = read_u8(&mut vm.it)?;
+							let args_cnt = read_u8(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							
+							if let Ok(method) = GCRef::<Method>::try_from(func.clone()) {
+								if !vm.call_native(program, heap, method.func.clone(), Some(method.this.clone()), args_start, args_cnt, rout)? {
+									return Err(error(format!("{} is not a method", func.repr())));
+								}
+							} else if let Ok(func) = GCRef::<Closure>::try_from(func.clone()) {
+								if program.chunks[usize::from(func.chunk_id)].is_generator {
+									let fiber = Fiber::new(program, func, vm.regs.reg_range(args_start, args_cnt));
+									*vm.regs.mut_reg(rout) = heap.make_value(Coroutine::new(fiber, true));
 								} else {
-									let idx = cur_call.reg_win.0 + (reg as usize);
-									let upv = heap.make_ref(Upvalue::new(idx));
-									cur_call.upvalues.insert(reg, upv.clone());
-									upv
+									vm.call(program, func, vm.regs.window_start + usize::from(args_start), Some(rout))?;
 								}
-							} else { // Upvalue points to upvalue
-								cur_call.closure.upvalues[(reg - MAX_REGISTERS) as usize].clone()
+							} else if !vm.call_native(program, heap, func.clone(), None, args_start, args_cnt, rout)? {
+								return Err(error(format!("Cannot call value {}", func.repr())));
 							}
-						}).collect();
-						*vm.regs.mut_reg(rout) = heap.make_value(Closure::new(chunk_id, upvalues));
-					},
-					InstrType::Call => {
-						let func = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?.clone();
-						let args_start = read_u8(&mut vm.it)?;
-						let args_cnt = read_u8(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						
-						if let Ok(method) = GCRef::<Method>::try_from(func.clone()) {
-							vm.call_native(heap, method.func.clone(), Some(method.this.clone()), args_start, args_cnt, rout)
-								.map_err(|_| error(format!("{} is not a method", func.repr())))?;
-						} else if let Ok(func) = GCRef::<Closure>::try_from(func.clone()) {
-							vm.call(program, func, args_start, Some(rout));
-						} else {
-							vm.call_native(heap, func.clone(), None, args_start, args_cnt, rout)
-								.map_err(|_| error(format!("Cannot call value {}", func.repr())))?;
-						}
-					},
-					InstrType::CallMethod => {
-						let ext_idx = read_u16(&mut vm.it)?;
-						let prop = read_u8(&mut vm.it)?;
-						let val = read_u8(&mut vm.it)?;
-						let args_start = read_u8(&mut vm.it)?;
-						let args_cnt = read_u8(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						
-						let this = vm.regs.reg_or_cst(vm.chunk, heap, val)?.clone();
-						let ns = GCRef::<Namespace>::try_from(vm.external.get(ext_idx as usize)
-							.ok_or_else(|| error_str("Invalid external value"))?.clone())
-							.map_err(|_| error_str("Invalid namespace"))?;
-						let func = ns.get(prop)?.clone();
-						vm.call_native(heap, func.clone(), Some(this), args_start, args_cnt, rout)
-							.map_err(|_| error(format!("Cannot call method {}", func.repr())))?;
-					},
-					InstrType::Ret => {
-						let rin = read_u8(&mut vm.it)?;
-						let temp = vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone();
-						
-						if vm.ret(program, temp)? {
-							return Ok(true);
-						}
-					}
-					InstrType::Jmp => {
-						let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
-						vm.it = iter_from(&vm.chunk.code, final_add);
-					},
-					InstrType::Jit => {
-						let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
-						let cond_val = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?;
-						let cond = bool::try_from(cond_val.deref())
-							.map_err(|_| error_str("Non-bool used in condition"))?;
-						if cond {
-							vm.it = iter_from(&vm.chunk.code, final_add);
-						}
-					},
-					InstrType::Jif => {
-						let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
-						let cond_val = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?;
-						let cond = bool::try_from(cond_val.deref())
-							.map_err(|_| error_str("Non-bool used in condition"))?;
-						if !cond {
-							vm.it = iter_from(&vm.chunk.code, final_add);
-						}
-					},
-					InstrType::Jin => {
-						let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
-						let val = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?;
-						if val.is_nil() {
+						},
+						InstrType::CallMethod => {
+							let ext_idx = read_u16(&mut vm.it)?;
+							let prop = read_u8(&mut vm.it)?;
+							let val = read_u8(&mut vm.it)?;
+							let args_start = read_u8(&mut vm.it)?;
+							let args_cnt = read_u8(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							
+							let this = vm.regs.reg_or_cst(vm.chunk, heap, val)?.clone();
+							let ns = GCRef::<Namespace>::try_from(vm.external.get(ext_idx as usize)
+								.ok_or_else(|| error_str("Invalid external value"))?.clone())
+								.map_err(|_| error_str("Invalid namespace"))?;
+							let func = ns.get(prop)?.clone();
+							if !vm.call_native(program, heap, func.clone(), Some(this), args_start, args_cnt, rout)? {
+								return Err(error(format!("Cannot call method {}", func.repr())));
+							}
+						},
+						InstrType::Ret => {
+							let rin = read_u8(&mut vm.it)?;
+							let temp = vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone();
+							
+							vm.ret(program, temp)?;
+						},
+						InstrType::Yield => {
+							let rin = read_u8(&mut vm.it)?;
+							let temp = vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone();
+							vm.suspend(program, temp, None)?;
+						},
+						InstrType::Jmp => {
+							let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
 							vm.it = iter_from(&vm.chunk.code, final_add);
-						}
-					},
-					InstrType::GetUp => {
-						let upv_idx = read_u8(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						let upv = vm.calls.last().unwrap().closure.upvalues[upv_idx as usize].clone();
-						*vm.regs.mut_reg(rout) = vm.regs.get_upvalue(upv);
-					},
-					InstrType::SetUp => {
-						let upv_idx = read_u8(&mut vm.it)?;
-						let rin = read_u8(&mut vm.it)?;
-						let upv = vm.calls.last().unwrap().closure.upvalues[upv_idx as usize].clone();
-						vm.regs.set_upvalue(upv, vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone());
-					},
-					InstrType::CloseUp => {
-						let reg = read_u8(&mut vm.it)?;
-						if let Some(upv) = vm.calls.last_mut().unwrap().upvalues.remove(&reg) { // If there is an upvalue at reg
-							let val = vm.regs.reg_or_cst(vm.chunk, heap, reg)?.clone();
-							upv.set_inside(val);
-						}
-					},
-					InstrType::GetExt => {
-						let ext_idx = read_u16(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						*vm.regs.mut_reg(rout) = vm.external.get(ext_idx as usize)
-							.ok_or_else(|| error_str("Invalid external value"))?.clone();
-					},
-					InstrType::ListNew => {
-						let rout = read_u8(&mut vm.it)?;
-						*vm.regs.mut_reg(rout) = heap.make_value(List::new());
-					},
-					InstrType::ListExtend => {
-						let list = read_u8(&mut vm.it)?;
-						let vals_start = read_u8(&mut vm.it)?;
-						let vals_cnt = read_u8(&mut vm.it)?;
-						let list = GCRef::<List>::try_from(vm.regs.reg_or_cst(vm.chunk, heap, list)?.deref().clone())
-							.map_err(|_| error_str("Cannot use ListExtend on non-List value"))?;
-						let vals = vm.regs.reg_range(vals_start, vals_cnt);
-						list.extend(vals);
-					},
-					InstrType::ListGet => {
-						let list = read_u8(&mut vm.it)?;
-						let index = read_u8(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						let list = GCRef::<List>::try_from(vm.regs.reg_or_cst(vm.chunk, heap, list)?.deref().clone())
-							.map_err(|_| error_str("Cannot index non-list value"))?;
-						let index = i32::try_from(vm.regs.reg_or_cst(vm.chunk, heap, index)?.deref())
-							.map_err(|_| error_str("Cannot index list with non-integer"))?;
-						let index = usize::try_from(index)
-							.map_err(|_| error_str("Cannot index list with negative integer"))?;
-						*vm.regs.mut_reg(rout) = list.get(index)?;
-					},
-					InstrType::ListSet => {
-						let list = read_u8(&mut vm.it)?;
-						let index = read_u8(&mut vm.it)?;
-						let rin = read_u8(&mut vm.it)?;
-						let list = GCRef::<List>::try_from(vm.regs.reg_or_cst(vm.chunk, heap, list)?.deref().clone())
-							.map_err(|_| error_str("Cannot index non-list value"))?;
-						let index = i32::try_from(vm.regs.reg_or_cst(vm.chunk, heap, index)?.deref())
-							.map_err(|_| error_str("Cannot index list with non-integer"))?;
-						let index = usize::try_from(index)
-							.map_err(|_| error_str("Cannot index list with negative integer"))?;
-						list.set(index, vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone())?;
-					},
-					InstrType::MakeMethod => {
-						let ext_idx = read_u16(&mut vm.it)?;
-						let prop = read_u8(&mut vm.it)?;
-						let val = read_u8(&mut vm.it)?;
-						let rout = read_u8(&mut vm.it)?;
-						
-						let this = vm.regs.reg_or_cst(vm.chunk, heap, val)?.clone();
-						let ns = GCRef::<Namespace>::try_from(vm.external.get(ext_idx as usize)
-							.ok_or_else(|| error_str("Invalid external value"))?.clone())
-							.map_err(|_| error_str("Invalid namespace"))?;
-						let func = ns.get(prop)?;
-						*vm.regs.mut_reg(rout) = heap.make_value(Method { this, func });
+						},
+						InstrType::Jit => {
+							let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
+							let cond_val = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?;
+							let cond = bool::try_from(cond_val.deref())
+								.map_err(|_| error_str("Non-bool used in condition"))?;
+							if cond {
+								vm.it = iter_from(&vm.chunk.code, final_add);
+							}
+						},
+						InstrType::Jif => {
+							let final_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
+							let cond_val = vm.regs.reg_or_cst(vm.chunk, heap, read_u8(&mut vm.it)?)?;
+							let cond = bool::try_from(cond_val.deref())
+								.map_err(|_| error_str("Non-bool used in condition"))?;
+							if !cond {
+								vm.it = iter_from(&vm.chunk.code, final_add);
+							}
+						},
+						InstrType::Iter => {
+							let (rin, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let val = vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone();
+							*vm.regs.mut_reg(rout) = if let Ok(list) = GCRef::<List>::try_from(val.clone()) {
+								heap.make_value(IteratorWrapper::new(VecIterator::new(list.get_copy())))
+							} else if GCRef::<IteratorWrapper>::try_from(val.clone()).is_ok() || GCRef::<Coroutine>::try_from(val.clone()).is_ok() {
+								val
+							} else {
+								return Err(error(format!("Cannot iterate over {}", val.repr())));
+							};
+						},
+						InstrType::IterNext => {
+							let end_add = read_rel_add(&mut vm.it, &vm.chunk.code)?;
+							let (iter, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let iter = vm.regs.reg_or_cst(vm.chunk, heap, iter)?.clone();
+							if !vm.iter_next(program, heap, iter, rout, Some(end_add))? {
+								vm.it = iter_from(&vm.chunk.code, end_add);
+							}
+						},
+						instr @ (InstrType::Range | InstrType::RangeIncl) => {
+							let (start, end, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let start = i64::try_from(vm.regs.reg_or_cst(vm.chunk, heap, start)?.deref())
+								.map_err(|_| error_str("Range bounds must be integers"))?;
+							let end = i64::try_from(vm.regs.reg_or_cst(vm.chunk, heap, end)?.deref())
+								.map_err(|_| error_str("Range bounds must be integers"))?;
+							let inclusive = matches!(instr, InstrType::RangeIncl);
+							*vm.regs.mut_reg(rout) = heap.make_value(IteratorWrapper::new(RangeIterator::new(start, end, inclusive)));
+						},
+						InstrType::GetUp => {
+							let upv_idx = read_u8(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							let upv = vm.calls.last().unwrap().closure.upvalues[upv_idx as usize].clone();
+							*vm.regs.mut_reg(rout) = vm.regs.get_upvalue(upv);
+						},
+						InstrType::SetUp => {
+							let upv_idx = read_u8(&mut vm.it)?;
+							let rin = read_u8(&mut vm.it)?;
+							let upv = vm.calls.last().unwrap().closure.upvalues[upv_idx as usize].clone();
+							vm.regs.set_upvalue(upv, vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone());
+						},
+						InstrType::CloseUp => {
+							let reg = read_u8(&mut vm.it)?;
+							if let Some(upv) = vm.calls.last_mut().unwrap().upvalues.remove(&reg) { // If there is an upvalue at reg
+								let val = vm.regs.reg_or_cst(vm.chunk, heap, reg)?.clone();
+								upv.set_inside(val);
+							}
+						},
+						InstrType::GetExt => {
+							let ext_idx = read_u16(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							*vm.regs.mut_reg(rout) = vm.external.get(ext_idx as usize)
+								.ok_or_else(|| error_str("Invalid external value"))?.clone();
+						},
+						InstrType::ListNew => {
+							let rout = read_u8(&mut vm.it)?;
+							*vm.regs.mut_reg(rout) = heap.make_value(List::new());
+						},
+						InstrType::ListExtend => {
+							let list = read_u8(&mut vm.it)?;
+							let vals_start = read_u8(&mut vm.it)?;
+							let vals_cnt = read_u8(&mut vm.it)?;
+							let list = GCRef::<List>::try_from(vm.regs.reg_or_cst(vm.chunk, heap, list)?.deref().clone())
+								.map_err(|_| error_str("Cannot use ListExtend on non-List value"))?;
+							let vals = vm.regs.reg_range(vals_start, vals_cnt);
+							list.extend(vals);
+							heap.update_size(&list)?;
+						},
+						InstrType::ListGet => {
+							let list = read_u8(&mut vm.it)?;
+							let index = read_u8(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							let list = GCRef::<List>::try_from(vm.regs.reg_or_cst(vm.chunk, heap, list)?.deref().clone())
+								.map_err(|_| error_str("Cannot index non-list value"))?;
+							let index = i64::try_from(vm.regs.reg_or_cst(vm.chunk, heap, index)?.deref())
+								.map_err(|_| error_str("Cannot index list with non-integer"))?;
+							let index = resolve_index(index, list.len())
+								.ok_or_else(|| error(format!("Can't get value at index {} in list of length {}", index, list.len())))?;
+							*vm.regs.mut_reg(rout) = list.get(index)?;
+						},
+						InstrType::ListSet => {
+							let list = read_u8(&mut vm.it)?;
+							let index = read_u8(&mut vm.it)?;
+							let rin = read_u8(&mut vm.it)?;
+							let list = GCRef::<List>::try_from(vm.regs.reg_or_cst(vm.chunk, heap, list)?.deref().clone())
+								.map_err(|_| error_str("Cannot index non-list value"))?;
+							let index = i64::try_from(vm.regs.reg_or_cst(vm.chunk, heap, index)?.deref())
+								.map_err(|_| error_str("Cannot index list with non-integer"))?;
+							let index = resolve_index(index, list.len())
+								.ok_or_else(|| error(format!("Can't set value at index {} in list of length {}", index, list.len())))?;
+							list.set(index, vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone())?;
+						},
+						InstrType::Slice => {
+							let (val, start, end, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let val = vm.regs.reg_or_cst(vm.chunk, heap, val)?.clone();
+							let start = vm.regs.reg_or_cst(vm.chunk, heap, start)?.clone();
+							let end = vm.regs.reg_or_cst(vm.chunk, heap, end)?.clone();
+							*vm.regs.mut_reg(rout) = if let Ok(list) = GCRef::<List>::try_from(val.clone()) {
+								let values = list.get_copy();
+								let (start, end) = slice_bounds(&start, &end, values.len())?;
+								let slice = List::new();
+								slice.extend(&values[start..end]);
+								heap.try_make_value(slice)?
+							} else if let Ok(s) = GCRef::<String>::try_from(val.clone()) {
+								let (start, end) = slice_bounds(&start, &end, s.chars().count())?;
+								heap.try_make_value(s.chars().skip(start).take(end - start).collect::<String>())?
+							} else {
+								return Err(error(format!("Cannot slice {}", val.repr())));
+							};
+						},
+						InstrType::MakeMethod => {
+							let ext_idx = read_u16(&mut vm.it)?;
+							let prop = read_u8(&mut vm.it)?;
+							let val = read_u8(&mut vm.it)?;
+							let rout = read_u8(&mut vm.it)?;
+							
+							let this = vm.regs.reg_or_cst(vm.chunk, heap, val)?.clone();
+							let ns = GCRef::<Namespace>::try_from(vm.external.get(ext_idx as usize)
+								.ok_or_else(|| error_str("Invalid external value"))?.clone())
+								.map_err(|_| error_str("Invalid namespace"))?;
+							let func = ns.get(prop)?;
+							*vm.regs.mut_reg(rout) = heap.make_value(Method { this, func });
+						},
+						InstrType::GetMember => {
+							let (rin, prop, rout) = (read_u8(&mut vm.it)?, read_u8(&mut vm.it)?, read_u8(&mut vm.it)?);
+							let module = vm.regs.reg_or_cst(vm.chunk, heap, rin)?.clone();
+							let module = GCRef::<Namespace>::try_from(module)
+								.map_err(|_| error_str("Cannot get member of non-module value"))?;
+							*vm.regs.mut_reg(rout) = module.get(prop)?;
+						},
+						#[allow(unreachable_patterns)]
+						i => unimplemented!("Unimplemented instruction: {:?}", i)
 					}
-					#[allow(unreachable_patterns)]
-					i => unimplemented!("Unimplemented instruction: {:?}", i)
+				} else { // implicit return
+					vm.ret(program, NIL)?;
 				}
-			} else { // implicit return
-				if vm.ret(program, NIL)? {
-					return Ok(true);
+				Ok(vm.host_result.is_some())
+			};
+			
+			let out_of_budget = exhausted.is_some();
+			let mut stop = match exhausted {
+				Some(outcome) => Err(budget_error(&outcome, vm.line_at(program, instr_pos))),
+				None => run_instr().and_then(|stop| {
+					heap.step()?;
+					Ok(stop)
+				}),
+			};
+			
+			if let Err(HissyError(ErrorType::Execution | ErrorType::OutOfFuel, _, line, trace)) = &mut stop {
+				if *line == 0 {
+					*line = vm.line_at(program, instr_pos);
 				}
+				// Unless the budget ran out first, the failed instruction was read
+				let pos = if out_of_budget { instr_pos } else { vm.pos().saturating_sub(1) };
+				vm.add_trace(program, pos, trace);
 			}
-			Ok(false)
-		};
+			
+			if stop.is_err() {
+				vm.unwind(program);
+			}
+			if stop? {
+				return Ok(Outcome::Finished(vm.host_result.take().unwrap()));
+			}
+		}
+	}
+}
+
+
+/// How a run of the main chunk ended, when it can be paused.
+/// 
+/// After running out of budget, the run can be continued with [`VM::continue_main`], or abandoned with [`VM::abort_main`].
+#[derive(Debug)]
+pub enum Outcome {
+	/// The main chunk returned this value.
+	Finished(Value),
+	/// The instruction budget set with [`VM::set_fuel`] is exhausted.
+	OutOfFuel,
+	/// The time limit set with [`VM::set_time_limit`] has passed.
+	OutOfTime,
+	/// The script called `exit` with this status code. Like when it finishes, the run is over.
+	Exited(i32),
+}
+
+// The error for running out of budget where execution cannot be paused
+fn budget_error(outcome: &Outcome, line: u16) -> HissyError {
+	let msg = if let Outcome::OutOfTime = outcome { "Time limit exceeded" } else { "Out of fuel" };
+	HissyError::new(ErrorType::OutOfFuel, String::from(msg), line)
+}
+
+// The error reporting a call to `exit` where the host expects a value
+fn exit_error(code: i32) -> HissyError {
+	error(format!("Script exited with code {}", code))
+}
+
+
+/// A Hissy virtual machine, holding the execution state of a compiled program between calls.
+/// 
+/// The VM must always be used with the [`GCHeap`] it was created with.
+pub struct VM<'a> {
+	program: &'a Program,
+	state: VMState<'a>,
+	args: GCRef<List>, // The `args` list of the prelude
+}
+
+impl<'a> VM<'a> {
+	/// Creates a VM for a program compiled against the prelude only, allocating its external values in the heap.
+	pub fn new(heap: &mut GCHeap, program: &'a Program) -> VM<'a> {
+		VM::with_environment(program, &Environment::new(heap))
+	}
+	
+	/// Creates a VM for a program compiled with [`Compiler::with_environment`](crate::compiler::Compiler::with_environment).
+	pub fn with_environment(program: &'a Program, env: &Environment) -> VM<'a> {
+		let mut state = VMState::new(program);
+		state.external = env.values();
+		state.fs_allowed = env.fs_allowed();
+		VM { program, state, args: env.args() }
+	}
+	
+	/// Seeds the random number generator used by the `random` module, making runs reproducible.
+	pub fn seed_random(&mut self, seed: u64) {
+		self.state.rng = Rng::new(seed);
+	}
+	
+	/// Replaces the clock used by the `time` module, which is the [`SystemClock`] by default.
+	pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+		self.state.clock = clock;
+	}
+	
+	/// Redirects the output of the script (`log`, `print`, `input` prompts), which goes to stdout by default.
+	/// 
+	/// Use an [`OutputBuffer`] to capture it.
+	pub fn set_output(&mut self, output: Box<dyn Write>) {
+		self.state.output = output;
+	}
+	
+	/// Replaces the source of the lines read by `input`, which is stdin by default.
+	pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+		self.state.input = input;
+	}
+	
+	/// Sets the contents of the `args` list of the script, usually its command-line arguments.
+	pub fn set_args(&mut self, heap: &mut GCHeap, args: &[String]) {
+		self.args.replace(args.iter().map(|arg| heap.make_value(arg.clone())).collect());
+	}
+	
+	/// Gives the script access to the file system through the `fs` module.
+	/// 
+	/// It is disabled by default, so that untrusted scripts stay sandboxed, unless
+	/// the VM was created with an environment allowing it (see [`Environment::allow_fs`]).
+	pub fn allow_fs(&mut self, allowed: bool) {
+		self.state.fs_allowed = allowed;
+	}
+	
+	/// Limits the number of instructions the VM executes before pausing, or removes the limit with `None`.
+	/// 
+	/// The fuel decreases as the script runs; [`VM::fuel`] tells how much is left.
+	pub fn set_fuel(&mut self, fuel: Option<u64>) {
+		self.state.fuel = fuel;
+	}
+	
+	pub fn fuel(&self) -> Option<u64> {
+		self.state.fuel
+	}
+	
+	/// Makes the VM pause once the given number of seconds have passed on its clock, or removes the limit with `None`.
+	/// 
+	/// The time is only checked every few instructions, so native functions which block, like `time.sleep`, can overrun it.
+	pub fn set_time_limit(&mut self, secs: Option<f64>) {
+		self.state.deadline = secs.map(|secs| self.state.clock.now() + secs);
+		self.state.steps = 0;
+	}
+	
+	/// Limits the number of nested calls, above which calling a function fails with a stack overflow error.
+	/// 
+	/// The limit applies separately to each coroutine. It is 10000 by default.
+	pub fn set_max_call_depth(&mut self, depth: usize) {
+		self.state.max_call_depth = depth;
+	}
+	
+	/// Limits the number of registers used by all the nested calls, above which calling a function fails
+	/// with a stack overflow error. Each call uses up to 128 registers, and the default limit is 2^20.
+	pub fn set_max_stack_size(&mut self, registers: usize) {
+		self.state.max_stack_size = registers;
+	}
+	
+	/// Limits the number of nested calls from native functions back into Hissy code, like the callbacks of `list.map`.
+	/// 
+	/// These calls recurse on the stack of the host thread, so the default limit of 100 is low,
+	/// and should be adjusted to the size of that stack.
+	pub fn set_max_native_depth(&mut self, depth: u32) {
+		self.state.max_native_depth = depth;
+	}
+	
+	/// Runs the main chunk of the program, and returns its return value.
+	/// 
+	/// If the budget set with [`VM::set_fuel`] or [`VM::set_time_limit`] runs out, the run is abandoned
+	/// with an [`ErrorType::OutOfFuel`] error; use [`VM::start_main`] to be able to pause it instead.
+	/// If the script calls `exit`, the run fails with an execution error, and [`VM::exit_code`] gives the code;
+	/// [`VM::start_main`] reports it as an [`Outcome::Exited`] instead.
+	pub fn run_main(&mut self, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		match self.start_main(heap)? {
+			Outcome::Finished(val) => Ok(val),
+			Outcome::Exited(code) => Err(exit_error(code)),
+			outcome => {
+				let err = budget_error(&outcome, self.state.line_at(self.program, self.state.pos()));
+				self.abort_main();
+				Err(err)
+			},
+		}
+	}
+	
+	/// Runs the main chunk of the program, until it returns, exits, or the budget runs out.
+	/// 
+	/// The budget is only enforced by pausing in the main chunk itself: when it runs out during a call
+	/// from native code (like the callback of `list.map`), the call fails with an [`ErrorType::OutOfFuel`] error.
+	pub fn start_main(&mut self, heap: &mut GCHeap) -> Result<Outcome, HissyError> {
+		if self.state.paused {
+			return Err(error_str("The main chunk is already running"));
+		}
+		let main = heap.make_ref(Closure::new(0, vec![]));
+		self.state.call(self.program, main, 0, None)?;
+		self.run_top(heap)
+	}
+	
+	// Runs the main chunk from the host, dropping its frames if it fails so that the VM can be reused
+	// The VM is only left paused when the run is paused
+	fn run_top(&mut self, heap: &mut GCHeap) -> Result<Outcome, HissyError> {
+		self.state.exit_code = None;
+		let res = self.state.run(self.program, heap);
+		if res.is_err() {
+			self.state.abort_calls(0);
+			if let Some(code) = self.state.exit_code {
+				return Ok(Outcome::Exited(code));
+			}
+		}
+		res
+	}
+	
+	/// Continues a run of the main chunk paused by running out of budget, usually after adding more.
+	pub fn continue_main(&mut self, heap: &mut GCHeap) -> Result<Outcome, HissyError> {
+		if !mem::replace(&mut self.state.paused, false) {
+			return Err(error_str("The main chunk is not paused"));
+		}
+		self.run_top(heap)
+	}
+	
+	/// Abandons a paused run of the main chunk, killing the coroutines it was running.
+	pub fn abort_main(&mut self) {
+		if mem::replace(&mut self.state.paused, false) {
+			self.state.unwind(self.program);
+			self.state.abort_calls(0);
+		}
+	}
+	
+	/// Gets the value of a top-level binding of the program, like a function defined by the script.
+	/// 
+	/// Returns `None` if there is no such binding, or if the main chunk has not returned yet.
+	pub fn get_global(&self, name: &str) -> Option<Value> {
+		let reg = self.program.globals.iter().find(|(name2, _)| name2 == name)?.1;
+		let main = self.state.main_bindings.as_ref()?;
+		Some(match main.upvalues.get(&reg) {
+			Some(upv) => self.state.regs.get_upvalue(upv.clone()),
+			None => main.regs[usize::from(reg)].clone(),
+		})
+	}
+	
+	/// Calls a closure or native function with the given arguments, and returns its result.
+	/// 
+	/// Calling a generator function returns a coroutine, to be driven with [`VM::resume`].
+	/// If the function calls `exit`, the call fails, and [`VM::exit_code`] gives the code.
+	pub fn call(&mut self, heap: &mut GCHeap, func: &Value, args: &[Value]) -> Result<Value, HissyError> {
+		self.state.exit_code = None;
+		self.state.call_value(self.program, heap, func.clone(), args.to_vec())
+	}
+	
+	/// Resumes a suspended coroutine, and returns the value it yields or returns.
+	/// 
+	/// On the first resume, the arguments are passed to the function of the coroutine.
+	/// Afterwards, the first argument becomes the result of the `suspend` call it is waiting in.
+	/// If the coroutine raises an error or calls `exit`, it is considered dead.
+	pub fn resume(&mut self, heap: &mut GCHeap, co: &GCRef<Coroutine>, args: &[Value]) -> Result<Value, HissyError> {
+		self.state.exit_code = None;
+		self.state.resume_from_host(self.program, heap, co.clone(), args)?
+			.ok_or_else(|| error_str("Cannot resume dead coroutine"))
+	}
+	
+	/// The status code the script passed to `exit`, if it exited during the last run, call or resume from the host.
+	pub fn exit_code(&self) -> Option<i32> {
+		self.state.exit_code
+	}
+}
+
+/// A handle on the running VM, given to native functions.
+/// 
+/// It gives access to the GC heap, and allows calling back into Hissy code.
+pub struct VMHandle<'a, 'b> {
+	pub heap: &'b mut GCHeap,
+	program: &'a Program,
+	state: &'b mut VMState<'a>,
+}
+
+impl<'a, 'b> VMHandle<'a, 'b> {
+	/// Calls a closure, method or native function with the given arguments, and returns its result.
+	pub fn call(&mut self, func: &Value, args: Vec<Value>) -> Result<Value, HissyError> {
+		self.state.call_value(self.program, self.heap, func.clone(), args)
+	}
+	
+	/// Gets the next value of an iterator or generator, or `None` if it is exhausted.
+	pub fn next(&mut self, iter: &Value) -> Result<Option<Value>, HissyError> {
+		self.state.next_value(self.program, self.heap, iter.clone())
+	}
+	
+	pub(crate) fn rng(&mut self) -> &mut Rng {
+		&mut self.state.rng
+	}
+	
+	pub(crate) fn clock(&mut self) -> &mut dyn Clock {
+		&mut *self.state.clock
+	}
+	
+	// Stops the script: the error unwinds it, and the host sees an exit with this status code
+	pub(crate) fn exit(&mut self, code: i32) -> HissyError {
+		self.state.exit_code = Some(code);
+		exit_error(code)
+	}
+	
+	// The message of the last failed file system operation, or an error if file system access is disabled
+	pub(crate) fn fs_error(&mut self) -> Result<&mut String, HissyError> {
+		if self.state.fs_allowed {
+			Ok(&mut self.state.fs_error)
+		} else {
+			Err(error_str("File system access is disabled"))
+		}
+	}
+	
+	/// The sink the script's output should be written to.
+	pub fn output(&mut self) -> &mut dyn Write {
+		&mut *self.state.output
+	}
+	
+	/// The source the script's input should be read from.
+	pub fn input(&mut self) -> &mut dyn BufRead {
+		&mut *self.state.input
+	}
+}
+
+/// Runs a compiled Hissy program, using an existing GC heap, and returns the value returned by its main chunk.
+/// 
+/// To keep interacting with the program afterwards, use a [`VM`] instead.
+pub fn run_program(heap: &mut GCHeap, program: &Program) -> Result<Value, HissyError> {
+	let mut vm = VM::new(heap, program);
+	let res = vm.run_main(heap)?;
+	drop(vm);
+	heap.collect();
+	Ok(res)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::convert::TryFrom;
+	use std::mem;
+	
+	use super::{VM, Outcome, FakeClock, gc::{GCHeap, GCRef}, value::Value, object::{Coroutine, CoroutineStatus}};
+	use crate::{HissyError, ErrorType};
+	use crate::compiler::Compiler;
+	
+	// Runs a script on a fresh VM, returning the representation of its result
+	pub(super) fn eval(code: &str) -> Result<String, HissyError> {
+		eval_with(code, |_, _| {})
+	}
+	
+	// Same as `eval`, letting the caller configure the VM before the script runs
+	pub(super) fn eval_with(code: &str, setup: impl FnOnce(&mut VM, &mut GCHeap)) -> Result<String, HissyError> {
+		let program = Compiler::new(true).compile_program(code)?;
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		setup(&mut vm, &mut heap);
+		vm.run_main(&mut heap).map(|val| val.repr())
+	}
+	
+	const COUNT: &str = "let count(n: Int) -> Iterator<Int>:
+	let i = 0
+	while i < n:
+		yield i
+		i = i + 1
+";
+	
+	#[test]
+	fn test_generators() {
+		assert_eq!(eval(&[COUNT, "let l = []\nfor x in count(3):\n\tl.add(x)\nreturn l\n"].concat()).unwrap(), "[0, 1, 2]");
+		assert_eq!(eval(&[COUNT, "let it = count(2)\nreturn [it.next(), it.next()]\n"].concat()).unwrap(), "[0, 1]");
+		assert_eq!(eval(&[COUNT, "let it = count(1)\nit.next()\nit.next()\n"].concat()).unwrap_err().1, "Iterator is exhausted");
 		
-		let mut stop = run_instr();
+		// Generators keep their upvalues, and can be nested
+		let code = "let k = 10\nlet gen() -> Iterator<Int>:\n\tfor i in count(2):\n\t\tk = k + 1\n\t\tyield k\nlet l = []\nfor x in gen():\n\tl.add(x)\nreturn [l, k]\n";
+		assert_eq!(eval(&[COUNT, code].concat()).unwrap(), "[[11, 12], 12]");
 		
-		if program.debug_info {
-			if let Err(HissyError(ErrorType::Execution, err, 0)) = stop {
-				let line_numbers = &vm.chunk.debug_info.line_numbers;
-				let line_idx = line_numbers.iter().position(|(pos2, _)| instr_pos < *pos2)
-					.unwrap_or_else(|| line_numbers.len()) - 1;
-				let line = line_numbers.get(line_idx)
-					.expect("Could not get line number of instruction").1;
-				stop = Err(HissyError(ErrorType::Execution, err, line));
-			}
+		// Errors inside a generator are reported at their line
+		let err = eval("let bad():\n\tyield [][0]\nfor x in bad():\n\tlog(x)\n").unwrap_err();
+		assert_eq!((err.1.as_str(), err.2), ("Can't get value at index 0 in list of length 0", 2));
+		
+		for code in ["yield 1\n", "let f() -> Int:\n\tyield 1\n", "let f() -> Iterator<Int>:\n\tyield \"a\"\n"].iter() {
+			assert!(matches!(eval(code).unwrap_err().0, ErrorType::Compilation), "{}", code);
 		}
+	}
+	
+	const WORKER: &str = "let seen = []
+let worker(n: Int) -> String:
+	let i = 0
+	while i < n:
+		seen.add(suspend(i))
+		i = i + 1
+	return \"done\"
+";
+	
+	#[test]
+	fn test_coroutines() {
+		let code = "let co = coroutine(worker)\nreturn [co.status(), co.resume(2), co.resume(\"a\"), co.resume(\"b\"), co.status(), seen]\n";
+		assert_eq!(eval(&[WORKER, code].concat()).unwrap(), "[\"suspended\", 0, 1, \"done\", \"dead\", [\"a\", \"b\"]]");
+		assert_eq!(eval(&[WORKER, "let co = coroutine(worker)\nco.resume(0)\nco.resume()\n"].concat()).unwrap_err().1,
+			"Cannot resume dead coroutine");
+		
+		// Suspending from a nested call suspends the whole coroutine
+		let code = "let both():\n\tworker(1)\n\tworker(1)\nlet co = coroutine(both)\nreturn [co.resume(), co.resume(), co.resume(), co.status()]\n";
+		assert_eq!(eval(&[WORKER, code].concat()).unwrap(), "[0, 0, nil, \"dead\"]");
+		let code = "let cos = [coroutine(worker)]\nlet peek() -> String:\n\treturn cos[0].status()\ncos[0] = coroutine(peek)\nreturn cos[0].resume()\n";
+		assert_eq!(eval(&[WORKER, code].concat()).unwrap(), "\"running\"");
+		
+		// A coroutine is still running while a coroutine it resumed runs
+		let code = "let cos = [coroutine(worker)]\nlet peek() -> String:\n\treturn cos[0].status()\nlet outer() -> Any:\n\treturn coroutine(peek).resume()\ncos[0] = coroutine(outer)\nreturn cos[0].resume()\n";
+		assert_eq!(eval(&[WORKER, code].concat()).unwrap(), "\"running\"");
+		
+		assert_eq!(eval("suspend(1)\n").unwrap_err().1, "Cannot yield outside of a coroutine");
+	}
+	
+	#[test]
+	fn test_host_resume() {
+		let code = "let worker(n: Int) -> List<Any>:\n\tlet seen = []\n\tfor i in range(0, n):\n\t\tseen.add(suspend(i))\n\treturn seen\nreturn coroutine(worker)\n";
+		let program = Compiler::new(false).compile_program(code).unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		let co = GCRef::<Coroutine>::try_from(vm.run_main(&mut heap).unwrap()).unwrap();
+		assert_eq!(vm.resume(&mut heap, &co, &[Value::from(2)]).unwrap().repr(), "0");
+		heap.collect();
+		assert_eq!(vm.resume(&mut heap, &co, &[Value::from(10)]).unwrap().repr(), "1");
+		assert_eq!(vm.resume(&mut heap, &co, &[Value::from(20)]).unwrap().repr(), "[10, 20]");
+		assert_eq!(co.status().name(), "dead");
+		assert_eq!(vm.resume(&mut heap, &co, &[]).unwrap_err().1, "Cannot resume dead coroutine");
 		
-		if stop? {
-			break;
+		// Coroutines which fail are dead
+		let program = Compiler::new(false).compile_program("let fail():\n\tsuspend([][0])\nreturn coroutine(fail)\n").unwrap();
+		let mut vm = VM::new(&mut heap, &program);
+		let co = GCRef::<Coroutine>::try_from(vm.run_main(&mut heap).unwrap()).unwrap();
+		assert_eq!(co.status(), CoroutineStatus::Suspended);
+		assert!(vm.resume(&mut heap, &co, &[]).is_err());
+		assert_eq!(co.status(), CoroutineStatus::Dead);
+	}
+	
+	#[test]
+	fn test_iterate_nil() {
+		assert_eq!(eval("let l = []\nfor x in [1, nil, 3]:\n\tl.add(x)\nreturn l\n").unwrap(), "[1, nil, 3]");
+		assert_eq!(eval("let l = []\nfor x in [nil, 2].iter():\n\tl.add(x)\nreturn l\n").unwrap(), "[nil, 2]");
+		assert_eq!(eval("let gen():\n\tyield nil\n\tyield 1\nlet l = []\nfor x in gen():\n\tl.add(x)\nreturn l\n").unwrap(), "[nil, 1]");
+		assert_eq!(eval("let it = [nil].iter()\nreturn it.next()\n").unwrap(), "nil");
+		assert_eq!(eval("let it = [nil].iter()\nit.next()\nit.next()\n").unwrap_err().1, "Iterator is exhausted");
+		assert_eq!(eval("let l = [1, \"a\"]\nfor x in l[0]:\n\tlog(x)\n").unwrap_err().1, "Cannot iterate over 1");
+	}
+	
+	#[test]
+	fn test_native_callbacks() {
+		assert_eq!(eval("let total = 0\nlet add(x: Int):\n\ttotal = total + x\n[1, 2, 3].for_each(add)\nreturn total\n").unwrap(), "6");
+		assert_eq!(eval("let l = [3, 1, 2]\nlet desc(a: Int, b: Int) -> Int:\n\treturn b - a\nl.sort_by(desc)\nreturn l\n").unwrap(), "[3, 2, 1]");
+		assert_eq!(eval("let odd(x: Int) -> Bool:\n\treturn x % 2 == 1\nreturn [1, 2, 3].filter(odd)\n").unwrap(), "[1, 3]");
+		assert_eq!(eval("let out = []\nlet gen():\n\tyield 1\n\tyield 2\ngen().for_each(out.add)\nreturn out\n").unwrap(), "[1, 2]");
+		
+		// Values built by callbacks survive collections
+		let code = "let l = []\nfor i in range(0, 5000):\n\tl.add(i)\nlet pair(x: Int) -> List<Int>:\n\treturn [x, x]\nreturn l.map(pair)[4999]\n";
+		assert_eq!(eval(code).unwrap(), "[4999, 4999]");
+		
+		// Errors propagate out of the native, at the line of the callback
+		let err = eval("let second(x: Int) -> Int:\n\treturn [x][1]\nreturn [1, 0].map(second)\n").unwrap_err();
+		assert_eq!((err.1.as_str(), err.2), ("Can't get value at index 1 in list of length 1", 2));
+		assert!(matches!(eval("let f(x: Int) -> Any:\n\treturn x\nreturn [1].filter(f)\n").unwrap_err().0, ErrorType::Compilation));
+		
+		// Natives cannot be suspended
+		assert_eq!(eval("let body():\n\t[1, 2].for_each(suspend)\nreturn coroutine(body).resume()\n").unwrap_err().1,
+			"Cannot yield across a native function call");
+	}
+	
+	const L: &str = "let l = [1, 2, 3, 4]\n";
+	
+	#[test]
+	fn test_slices_and_ranges() {
+		assert_eq!(eval(&[L, "return [l[1:3], l[:2], l[2:], l[-3:-1], l[4:]]\n"].concat()).unwrap(), "[[2, 3], [1, 2], [3, 4], [2, 3], []]");
+		assert_eq!(eval(&[L, "l[-1] = 9\nreturn [l[-4], l]\n"].concat()).unwrap(), "[1, [1, 2, 3, 9]]");
+		assert_eq!(eval("let s = \"h\u{e9}llo\"\nreturn [s[1:-1], s[:1], s[1:2]]\n").unwrap(), "[\"\u{e9}ll\", \"h\", \"\u{e9}\"]");
+		assert_eq!(eval("return [(0..3).collect(), (0..=3).collect(), (3..1).collect(), (0 - 2..0).collect()]\n").unwrap(),
+			"[[0, 1, 2], [0, 1, 2, 3], [], [-2, -1]]");
+		assert_eq!(eval("let t = 0\nfor i in 1..=100:\n\tt = t + i\nreturn t\n").unwrap(), "5050");
+		
+		let errors = [
+			("return l[1:5]\n", "Slice bound 5 is out of bounds for length 4"),
+			("return l[-5:]\n", "Slice bound -5 is out of bounds for length 4"),
+			("return l[3:1]\n", "Slice start 3 is after its end 1"),
+			("return l[4]\n", "Can't get value at index 4 in list of length 4"),
+			("l[-5] = 0\n", "Can't set value at index -5 in list of length 4"),
+		];
+		for (code, expected) in errors.iter() {
+			assert_eq!(eval(&[L, code].concat()).unwrap_err().1, *expected, "{}", code);
 		}
+	}
+	
+	const SCRIPT: &str = "
+let count = 0
+let on_event(weight: Int) -> Int:
+	count = count + 1
+	return count // weight
+let make_adder(n: Int) -> Any:
+	let add(x: Int) -> Int:
+		return x + n
+	return add
+return \"done\"
+";
+	
+	const SHARED: &str = "let n = 1
+let get() -> Int:
+	return n
+let bump():
+	n = n + 10
+let before = get()
+n = 5
+let after = get()
+bump()
+";
+	
+	#[test]
+	fn test_top_level_captures() {
+		// Closures share top-level locals with the main chunk, both ways
+		assert_eq!(eval(&[SHARED, "return [before, after, n, get()]\n"].concat()).unwrap(), "[1, 5, 15, 15]");
 		
-		heap.step();
+		// and keep sharing them with each other once it has returned
+		let program = Compiler::new(false).compile_program(SHARED).unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.run_main(&mut heap).unwrap();
+		let (get, bump) = (vm.get_global("get").unwrap(), vm.get_global("bump").unwrap());
+		vm.call(&mut heap, &bump, &[]).unwrap();
+		heap.collect();
+		assert_eq!(vm.call(&mut heap, &get, &[]).unwrap().repr(), "25");
+		assert_eq!(vm.get_global("n").unwrap().repr(), "25");
 	}
 	
-	vm.regs.free_all();
-	heap.collect();
+	#[test]
+	fn test_host_calls() {
+		let program = Compiler::new(false).compile_program(SCRIPT).unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		assert!(vm.get_global("count").is_none());
+		assert_eq!(vm.run_main(&mut heap).unwrap().repr(), "\"done\"");
+		assert!(vm.get_global("missing").is_none());
+		
+		let on_event = vm.get_global("on_event").unwrap();
+		assert_eq!(vm.call(&mut heap, &on_event, &[Value::from(1)]).unwrap().repr(), "1");
+		assert_eq!(vm.call(&mut heap, &on_event, &[Value::from(1)]).unwrap().repr(), "2");
+		assert_eq!(vm.get_global("count").unwrap().repr(), "2");
+		assert!(vm.call(&mut heap, &on_event, &[Value::from(0)]).is_err());
+		
+		let make_adder = vm.get_global("make_adder").unwrap();
+		let add = vm.call(&mut heap, &make_adder, &[Value::from(10)]).unwrap();
+		heap.collect();
+		assert_eq!(vm.call(&mut heap, &add, &[Value::from(5)]).unwrap().repr(), "15");
+		assert_eq!(vm.get_global("count").unwrap().repr(), "3");
+	}
+	
+	#[test]
+	fn test_budget() {
+		let mut heap = GCHeap::new();
+		let program = Compiler::new(true).compile_program("let n = 0\nwhile n < 1000:\n\tn = n + 1\nreturn n\n").unwrap();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.set_fuel(Some(100));
+		let mut outcome = vm.start_main(&mut heap).unwrap();
+		let mut pauses = 0;
+		while let Outcome::OutOfFuel = outcome {
+			assert_eq!(vm.fuel(), Some(0));
+			pauses += 1;
+			vm.set_fuel(Some(100));
+			outcome = vm.continue_main(&mut heap).unwrap();
+		}
+		assert!(pauses > 10);
+		assert!(matches!(outcome, Outcome::Finished(val) if val.repr() == "1000"));
+		assert!(vm.continue_main(&mut heap).is_err());
+		
+		let program = Compiler::new(true).compile_program("while true:\n\ttime.sleep(0.5)\n").unwrap();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.set_clock(Box::new(FakeClock::new(0.0)));
+		vm.set_time_limit(Some(10.0));
+		assert!(matches!(vm.start_main(&mut heap).unwrap(), Outcome::OutOfTime));
+		vm.abort_main();
+		vm.set_time_limit(Some(5.0));
+		let err = vm.run_main(&mut heap).unwrap_err();
+		assert!(matches!(err.0, ErrorType::OutOfFuel));
+		assert_eq!((err.1.as_str(), err.2), ("Time limit exceeded", 2));
+		
+		// Calls from native code cannot be paused
+		let program = Compiler::new(true).compile_program("let spin(x: Int) -> Int:\n\twhile true:\n\t\tx = x + 1\n\treturn x\nreturn [1].map(spin)\n").unwrap();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.set_fuel(Some(1000));
+		let err = vm.start_main(&mut heap).unwrap_err();
+		assert!(matches!(err.0, ErrorType::OutOfFuel));
+		assert_eq!(err.1, "Out of fuel");
+	}
+	
+	#[test]
+	fn test_memory_limit() {
+		let mut heap = GCHeap::new();
+		heap.set_memory_limit(Some(1 << 20));
+		let run = |heap: &mut GCHeap, code: &str| {
+			let program = Compiler::new(true).compile_program(code).unwrap();
+			let res = VM::new(heap, &program).run_main(heap).map(|val| val.repr());
+			heap.collect();
+			res
+		};
+		assert_eq!(run(&mut heap, "let l = [0]\nfor i in 1..10000:\n\tl.add(i)\nreturn l.size()\n").unwrap(), "10000");
+		
+		let err = run(&mut heap, "let l = [0]\nwhile true:\n\tl.add(1)\n").unwrap_err();
+		assert!(err.1.starts_with("Out of memory"));
+		assert_eq!(err.2, 3);
+		let err = run(&mut heap, "let s = \"ab\"\nwhile true:\n\ts = [s, s].join(\"\")\n").unwrap_err();
+		assert!(err.1.starts_with("Out of memory"));
+		assert!(heap.used_memory() < 1 << 16);
+	}
 	
-	Ok(())
+	#[test]
+	fn test_memory_limit_in_natives() {
+		let mut heap = GCHeap::new();
+		heap.set_memory_limit(Some(1 << 20));
+		let codes = [
+			"return (0..3000000).collect().size()\n",
+			"let id(n: Int) -> Int:\n\treturn n\nlet l = (0..100000).collect()\nreturn l.map(id).map(id).size()\n",
+			"let l = (0..100000).collect()\nreturn l.join(\"----------\")\n",
+			"let l = (0..100000).collect()\nreturn json.stringify([l, l, l], 0)\n",
+			"return 3 ^ 20000000\n",
+			"return 1 << 20000000\n",
+		];
+		for code in codes.iter() {
+			let program = Compiler::new(true).compile_program(code).unwrap();
+			let err = VM::new(&mut heap, &program).run_main(&mut heap).unwrap_err();
+			assert!(err.1.starts_with("Out of memory"), "{}", err.1);
+			// Growing objects may double their buffer once past the limit, but no more
+			assert!(heap.used_memory() <= 2 << 20, "{} bytes used", heap.used_memory());
+			heap.collect();
+		}
+	}
+	
+	#[test]
+	fn test_stack_overflow() {
+		let mut heap = GCHeap::new();
+		let program = Compiler::new(true).compile_program("let f(n: Int) -> Int:\n\tif n == 0:\n\t\treturn 0\n\treturn f(n - 1) + 1\nlet g(n: Int) -> Int:\n\treturn [n + 1].map(g)[0]\nlet gen() -> Iterator<Int>:\n\tyield 1\n").unwrap();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.run_main(&mut heap).unwrap();
+		let f = vm.get_global("f").unwrap();
+		let err = vm.call(&mut heap, &f, &[Value::from(-1)]).unwrap_err();
+		assert_eq!((err.1.as_str(), err.2), ("Stack overflow in function f", 4));
+		
+		vm.set_max_call_depth(50);
+		assert_eq!(vm.call(&mut heap, &f, &[Value::from(40)]).unwrap().repr(), "40");
+		assert!(vm.call(&mut heap, &f, &[Value::from(60)]).is_err());
+		vm.set_max_stack_size(30);
+		assert!(vm.call(&mut heap, &f, &[Value::from(40)]).is_err());
+		vm.set_max_stack_size(1000);
+		assert_eq!(vm.call(&mut heap, &f, &[Value::from(40)]).unwrap().repr(), "40");
+		
+		vm.set_max_native_depth(10);
+		let g = vm.get_global("g").unwrap();
+		assert_eq!(vm.call(&mut heap, &g, &[Value::from(0)]).unwrap_err().1, "Stack overflow in function g");
+		vm.set_max_native_depth(0);
+		let co = GCRef::<Coroutine>::try_from(vm.call(&mut heap, &vm.get_global("gen").unwrap(), &[]).unwrap()).unwrap();
+		assert_eq!(vm.resume(&mut heap, &co, &[]).unwrap_err().1, "Stack overflow in function gen");
+	}
+	
+	#[test]
+	fn test_stack_trace() {
+		let code = "let g(x: Int) -> Int:\n\treturn 1 // x\nlet f(x: Int) -> Int:\n\treturn [x].map(g)[0]\nf(0)\n";
+		let mut heap = GCHeap::new();
+		let mut run = |debug_info: bool| {
+			let program = Compiler::new(debug_info).compile_program(code).unwrap();
+			VM::new(&mut heap, &program).run_main(&mut heap).unwrap_err()
+		};
+		
+		let err = run(true);
+		let frames: Vec<(&str, u16)> = err.trace().iter().map(|frame| (frame.name.as_deref().unwrap(), frame.line)).collect();
+		assert_eq!(frames, vec![("g", 2), ("f", 4), ("<main>", 5)]);
+		assert!(err.to_string().ends_with("Integer division by zero\n  in g at line 2\n  in f at line 4\n  in <main> at line 5"));
+		let chunks: Vec<usize> = err.trace().iter().map(|frame| frame.chunk).collect();
+		
+		let err = run(false);
+		assert!(err.trace().iter().all(|frame| frame.name.is_none() && frame.line == 0));
+		assert_eq!(err.trace().iter().map(|frame| frame.chunk).collect::<Vec<usize>>(), chunks);
+		assert!(err.to_string().ends_with(&format!("\n  in chunk #{}\n  in chunk #{}\n  in chunk #0", chunks[0], chunks[1])));
+	}
+	
+	const FAILING: &str = "let l = [0]\nfor i in 1..10000:\n\tl.add(i)\nlet f(n: Int) -> Int:\n\treturn 1 // n\nreturn f(0)\n";
+	
+	#[test]
+	fn test_failed_main_twice() {
+		let program = Compiler::new(false).compile_program(FAILING).unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.set_max_call_depth(5);
+		for _ in 0..3 {
+			assert_eq!(vm.run_main(&mut heap).unwrap_err().1, "Integer division by zero");
+		}
+	}
+	
+	#[test]
+	fn test_failed_main_memory() {
+		let program = Compiler::new(false).compile_program(FAILING).unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		assert!(vm.run_main(&mut heap).is_err());
+		assert!(heap.used_memory() > 10000 * mem::size_of::<Value>());
+		heap.collect();
+		assert!(heap.used_memory() < 10000 * mem::size_of::<Value>());
+	}
+	
+	#[test]
+	fn test_budget_error() {
+		let program = Compiler::new(false).compile_program("let n = 0\nwhile n < 300:\n\tn = n + 1\nreturn 1 // (n - 300)\n").unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.set_max_call_depth(1);
+		vm.set_fuel(Some(100));
+		let mut res = vm.start_main(&mut heap);
+		while let Ok(Outcome::OutOfFuel) = res {
+			vm.set_fuel(Some(100));
+			res = vm.continue_main(&mut heap);
+		}
+		assert_eq!(res.unwrap_err().1, "Integer division by zero");
+		assert!(vm.continue_main(&mut heap).is_err());
+		
+		// The failed run left nothing behind
+		vm.set_fuel(None);
+		assert_eq!(vm.start_main(&mut heap).unwrap_err().1, "Integer division by zero");
+	}
 }
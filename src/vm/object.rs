@@ -1,19 +1,42 @@
 
 use std::cell::{Cell, RefCell};
-use std::ops::{Deref, DerefMut};
-use std::fmt;
+use std::ops::Deref;
+use std::convert::TryFrom;
+use std::{fmt, mem};
+use std::collections::HashMap;
 
 use crate::{HissyError, ErrorType};
 use super::value::Value;
-use super::gc::{GCHeap, Traceable, GC, GCRef};
+use super::gc::{Traceable, GC, GCRef};
+use super::{Fiber, VMHandle, HostFunction};
 
 
 fn error(s: String) -> HissyError {
-	HissyError(ErrorType::Execution, s, 0)
+	HissyError::new(ErrorType::Execution, s, 0)
 }
 
 
-impl Traceable for String {}
+impl Traceable for String {
+	fn heap_size(&self) -> usize {
+		self.capacity()
+	}
+}
+
+/// An integer which does not fit inline in a `Value`.
+#[derive(Clone, PartialEq)]
+pub struct BigInt(pub num_bigint::BigInt);
+
+impl Traceable for BigInt {
+	fn heap_size(&self) -> usize {
+		(self.0.bits() / 8) as usize
+	}
+}
+
+impl fmt::Debug for BigInt {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
 
 impl Traceable for Vec<Value> {
 	fn touch(&self, initial: bool) {
@@ -21,6 +44,10 @@ impl Traceable for Vec<Value> {
 			el.touch(initial);
 		}
 	}
+	
+	fn heap_size(&self) -> usize {
+		self.capacity() * mem::size_of::<Value>()
+	}
 }
 
 impl<T: GC> Traceable for Vec<GCRef<T>> {
@@ -29,6 +56,10 @@ impl<T: GC> Traceable for Vec<GCRef<T>> {
 			el.touch(initial);
 		}
 	}
+	
+	fn heap_size(&self) -> usize {
+		self.capacity() * mem::size_of::<GCRef<T>>()
+	}
 }
 
 #[derive(Clone)]
@@ -52,6 +83,14 @@ impl Upvalue {
 		val.touch(true);
 		self.0.replace(UpvalueData::OnHeap(val));
 	}
+	
+	// Moves a closed upvalue back onto the stack, returning the value to place there
+	pub fn reopen(&self, stack_idx: usize) -> Value {
+		match self.0.replace(UpvalueData::OnStack(stack_idx)) {
+			UpvalueData::OnHeap(val) => val.clone(),
+			UpvalueData::OnStack(_) => panic!("Trying to reopen an open upvalue"),
+		}
+	}
 }
 
 impl Traceable for Upvalue {
@@ -88,6 +127,10 @@ impl Traceable for Closure {
 	fn touch(&self, initial: bool) {
 		self.upvalues.touch(initial);
 	}
+	
+	fn heap_size(&self) -> usize {
+		self.upvalues.heap_size()
+	}
 }
 
 impl fmt::Debug for Closure {
@@ -97,21 +140,27 @@ impl fmt::Debug for Closure {
 }
 
 
-pub type HissyFun = dyn FnMut(&mut GCHeap, Vec<Value>) -> Result<Value, HissyError>;
+// Native functions may be re-entered through callbacks, so they cannot take &mut self
+pub type HissyFun = dyn Fn(&mut VMHandle, Vec<Value>) -> Result<Value, HissyError>;
 
 pub struct NativeFunction {
-	pub fun: Box<RefCell<HissyFun>>
+	pub fun: Box<HissyFun>
 }
 
 impl NativeFunction {
-	pub(crate) fn new(fun: impl FnMut(&mut GCHeap, Vec<Value>) -> Result<Value, HissyError> + 'static) -> NativeFunction {
+	pub fn new(fun: impl Fn(&mut VMHandle, Vec<Value>) -> Result<Value, HissyError> + 'static) -> NativeFunction {
 		NativeFunction {
-			fun: Box::new(RefCell::new(fun)),
+			fun: Box::new(fun),
 		}
 	}
 	
-	pub fn call(&self, heap: &mut GCHeap, args: Vec<Value>) -> Result<Value, HissyError> {
-		self.fun.borrow_mut().deref_mut()(heap, args)
+	/// Wraps a Rust function taking and returning convertible values, see [`HostFunction`].
+	pub fn from_fn<Args, F: HostFunction<Args>>(fun: F) -> NativeFunction {
+		NativeFunction::new(move |vm, args| fun.call(vm.heap, args))
+	}
+	
+	pub fn call(&self, vm: &mut VMHandle, args: Vec<Value>) -> Result<Value, HissyError> {
+		(self.fun)(vm, args)
 	}
 }
 
@@ -124,6 +173,48 @@ impl fmt::Debug for NativeFunction {
 }
 
 
+/// A built-in function implemented by the VM itself, because it acts on the execution state.
+#[derive(Clone, Copy)]
+pub(crate) enum Intrinsic {
+	IterNext,
+	MakeCoroutine,
+	Resume,
+	Suspend,
+}
+
+impl Traceable for Intrinsic {}
+
+impl fmt::Debug for Intrinsic {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "<function>")
+	}
+}
+
+
+/// Resolves a possibly negative index (counting from the end) into a sequence of length `len`.
+/// Returns `None` if it falls outside of `0..=len`.
+pub fn resolve_index(idx: i64, len: usize) -> Option<usize> {
+	let idx = if idx < 0 { idx.checked_add(len as i64)? } else { idx };
+	usize::try_from(idx).ok().filter(|idx| *idx <= len)
+}
+
+/// Resolves the bounds of a slice into a sequence of length `len`. A `nil` start or end
+/// stands for the start or end of the sequence.
+pub fn slice_bounds(start: &Value, end: &Value, len: usize) -> Result<(usize, usize), HissyError> {
+	let resolve = |bound: &Value, default: usize| {
+		if bound.is_nil() {
+			return Ok(default);
+		}
+		let idx = i64::try_from(bound).map_err(|_| error(format!("Expected integer slice bound, got {}", bound.repr())))?;
+		resolve_index(idx, len).ok_or_else(|| error(format!("Slice bound {} is out of bounds for length {}", idx, len)))
+	};
+	let (start, end) = (resolve(start, 0)?, resolve(end, len)?);
+	if start > end {
+		return Err(error(format!("Slice start {} is after its end {}", start, end)));
+	}
+	Ok((start, end))
+}
+
 #[derive(Default)]
 pub struct List {
 	data: RefCell<Vec<Value>>
@@ -156,19 +247,67 @@ impl List {
 		let mut data = self.data.borrow_mut();
 		let val2 = data.get_mut(idx)
 			.ok_or_else(|| error(format!("Can't set value at index {} in list of length {}", idx, self.len())))?;
+		val.touch(true);
 		*val2 = val;
 		Ok(())
 	}
 	
+	pub fn insert(&self, idx: usize, val: Value) -> Result<(), HissyError> {
+		let mut data = self.data.borrow_mut();
+		if idx > data.len() {
+			return Err(error(format!("Can't insert value at index {} in list of length {}", idx, data.len())));
+		}
+		val.touch(true);
+		data.insert(idx, val);
+		Ok(())
+	}
+	
+	pub fn remove(&self, idx: usize) -> Result<Value, HissyError> {
+		let mut data = self.data.borrow_mut();
+		if idx >= data.len() {
+			return Err(error(format!("Can't remove value at index {} in list of length {}", idx, data.len())));
+		}
+		let val = data.remove(idx).clone(); // Cloning makes the value a root again
+		shrink(&mut data);
+		Ok(val)
+	}
+	
+	pub fn pop(&self) -> Option<Value> {
+		let mut data = self.data.borrow_mut();
+		let val = data.pop().as_ref().cloned();
+		shrink(&mut data);
+		val
+	}
+	
+	pub fn clear(&self) {
+		*self.data.borrow_mut() = vec![];
+	}
+	
 	pub fn get_copy(&self) -> Vec<Value> {
 		self.data.borrow().clone()
 	}
+	
+	pub fn replace(&self, values: Vec<Value>) {
+		values.touch(true);
+		*self.data.borrow_mut() = values;
+	}
+}
+
+// Gives back the memory of a list which shrank a lot, so that it is no longer accounted for
+fn shrink(data: &mut Vec<Value>) {
+	if data.len() < data.capacity() / 4 {
+		data.shrink_to_fit();
+	}
 }
 
 impl Traceable for List {
 	fn touch(&self, initial: bool) {
 		self.data.borrow().touch(initial);
 	}
+	
+	fn heap_size(&self) -> usize {
+		self.data.borrow().heap_size()
+	}
 }
 
 impl fmt::Debug for List {
@@ -185,6 +324,79 @@ impl fmt::Debug for List {
 }
 
 
+/// A map from strings to values, which keeps its keys in insertion order.
+#[derive(Default)]
+pub struct Map {
+	entries: RefCell<Vec<(String, Value)>>,
+	index: RefCell<HashMap<String, usize>>,
+}
+
+impl Map {
+	pub fn new() -> Map {
+		Map::default()
+	}
+	
+	pub fn len(&self) -> usize {
+		self.entries.borrow().len()
+	}
+	
+	/// Sets the value of a key, adding it at the end if it is new.
+	pub fn set(&self, key: String, val: Value) {
+		val.touch(true);
+		let mut entries = self.entries.borrow_mut();
+		let mut index = self.index.borrow_mut();
+		if let Some(&idx) = index.get(&key) {
+			entries[idx].1 = val;
+		} else {
+			index.insert(key.clone(), entries.len());
+			entries.push((key, val));
+		}
+	}
+	
+	pub fn get(&self, key: &str) -> Option<Value> {
+		let idx = *self.index.borrow().get(key)?;
+		Some(self.entries.borrow()[idx].1.clone())
+	}
+	
+	pub fn contains_key(&self, key: &str) -> bool {
+		self.index.borrow().contains_key(key)
+	}
+	
+	pub fn get_entries(&self) -> Vec<(String, Value)> {
+		self.entries.borrow().clone()
+	}
+}
+
+impl Traceable for Map {
+	fn touch(&self, initial: bool) {
+		for (_, val) in self.entries.borrow().iter() {
+			val.touch(initial);
+		}
+	}
+	
+	fn heap_size(&self) -> usize {
+		// Keys are stored both in the entries and the index
+		let entries = self.entries.borrow();
+		let keys: usize = entries.iter().map(|(key, _)| 2 * key.capacity()).sum();
+		keys + entries.capacity() * mem::size_of::<(String, Value)>()
+			+ self.index.borrow().capacity() * mem::size_of::<(String, usize)>()
+	}
+}
+
+impl fmt::Debug for Map {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{{")?;
+		for (i, (key, val)) in self.entries.borrow().iter().enumerate() {
+			write!(f, "{:?}: {}", key, val.repr())?;
+			if i != self.len()-1 {
+				write!(f, ", ")?;
+			}
+		}
+		write!(f, "}}")
+	}
+}
+
+
 pub struct Namespace(pub Vec<Value>);
 
 impl Namespace {
@@ -198,6 +410,10 @@ impl Traceable for Namespace {
 	fn touch(&self, initial: bool) {
 		self.0.touch(initial);
 	}
+	
+	fn heap_size(&self) -> usize {
+		self.0.heap_size()
+	}
 }
 
 impl fmt::Debug for Namespace {
@@ -226,28 +442,42 @@ impl fmt::Debug for Method {
 }
 
 
+/// An iterator over Hissy values; exhaustion is signalled by `None`, so `nil` is a valid element.
+/// 
+/// `next` takes `&self`, since the GC may need to trace an iterator while it is calling back into Hissy code.
 pub trait GCIterator {
-	fn next(&mut self, heap: &mut GCHeap) -> Option<Value>;
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError>;
 	fn touch(&self, _initial: bool) {}
 }
 
-impl<T: Iterator<Item = T2>, T2: Into<Value>> GCIterator for T {
-	fn next(&mut self, _heap: &mut GCHeap) -> Option<Value> { self.next().map(|v| v.into()) }
+impl<T: Iterator<Item = T2>, T2: Into<Value>> GCIterator for RefCell<T> {
+	fn next(&self, _vm: &mut VMHandle) -> Result<Option<Value>, HissyError> { Ok(self.borrow_mut().next().map(|v| v.into())) }
 }
 
 pub struct IteratorWrapper {
-	pub iter: Box<RefCell<dyn GCIterator>>,
+	pub iter: Box<dyn GCIterator>,
+	running: Cell<bool>,
 }
 
 impl IteratorWrapper {
-	pub fn next(&self, heap: &mut GCHeap) -> Option<Value> {
-		self.iter.borrow_mut().next(heap)
+	pub fn new(iter: impl GCIterator + 'static) -> IteratorWrapper {
+		IteratorWrapper { iter: Box::new(iter), running: Cell::new(false) }
+	}
+	
+	pub fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		// Iterators may call back into Hissy code, which could try to advance them again
+		if self.running.replace(true) {
+			return Err(error(String::from("Iterator is already running")));
+		}
+		let res = self.iter.next(vm);
+		self.running.set(false);
+		res
 	}
 }
 
 impl Traceable for IteratorWrapper {
 	fn touch(&self, initial: bool) {
-		self.iter.borrow_mut().touch(initial);
+		self.iter.touch(initial);
 	}
 }
 
@@ -258,6 +488,141 @@ impl fmt::Debug for IteratorWrapper {
 }
 
 
+enum CoroutineState {
+	Created(Fiber),
+	Suspended(Fiber),
+	Running,
+	Finished,
+}
+
+/// The status of a coroutine, as reported to scripts and to the host.
+/// 
+/// Scripts get it from `status()` as one of the strings returned by `name`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CoroutineStatus {
+	/// Not started yet, or paused by `suspend`: it can be resumed.
+	Suspended,
+	/// Executing, or waiting for a coroutine it resumed to suspend or finish.
+	Running,
+	/// Returned from its function, or stopped by an error: it cannot be resumed.
+	Dead,
+}
+
+impl CoroutineStatus {
+	pub fn name(self) -> &'static str {
+		match self {
+			CoroutineStatus::Suspended => "suspended",
+			CoroutineStatus::Running => "running",
+			CoroutineStatus::Dead => "dead",
+		}
+	}
+}
+
+/// A suspendable line of execution, with its own register stack and call stack.
+/// 
+/// Coroutines are created by the `coroutine` function, and by calls to generator functions.
+pub struct Coroutine(RefCell<CoroutineState>);
+
+impl Coroutine {
+	// Creates a coroutine; if it is not started, the arguments of its first resume are passed to the function
+	pub(super) fn new(fiber: Fiber, started: bool) -> Coroutine {
+		let state = if started { CoroutineState::Suspended(fiber) } else { CoroutineState::Created(fiber) };
+		Coroutine(RefCell::new(state))
+	}
+	
+	pub fn status(&self) -> CoroutineStatus {
+		match self.0.borrow().deref() {
+			CoroutineState::Created(_) | CoroutineState::Suspended(_) => CoroutineStatus::Suspended,
+			CoroutineState::Running => CoroutineStatus::Running,
+			CoroutineState::Finished => CoroutineStatus::Dead,
+		}
+	}
+	
+	// Takes the fiber out to resume it, along with whether it was started, or returns None if the coroutine is finished
+	pub(super) fn take(&self) -> Result<Option<(Fiber, bool)>, HissyError> {
+		let (mut fiber, started) = match self.0.replace(CoroutineState::Running) {
+			CoroutineState::Created(fiber) => (fiber, false),
+			CoroutineState::Suspended(fiber) => (fiber, true),
+			CoroutineState::Running => return Err(error(String::from("Coroutine is already running"))),
+			CoroutineState::Finished => {
+				self.0.replace(CoroutineState::Finished);
+				return Ok(None);
+			},
+		};
+		fiber.root();
+		Ok(Some((fiber, started)))
+	}
+	
+	// The chunk of the function the coroutine will continue in, unless it is running or finished
+	pub(super) fn chunk_id(&self) -> Option<u8> {
+		match self.0.borrow().deref() {
+			CoroutineState::Created(fiber) | CoroutineState::Suspended(fiber) => fiber.calls.last().map(|call| call.closure.chunk_id),
+			CoroutineState::Running | CoroutineState::Finished => None,
+		}
+	}
+	
+	pub(super) fn suspend(&self, fiber: Fiber) {
+		fiber.touch(true);
+		self.0.replace(CoroutineState::Suspended(fiber));
+	}
+	
+	pub(super) fn finish(&self) {
+		self.0.replace(CoroutineState::Finished);
+	}
+}
+
+impl Traceable for Coroutine {
+	fn touch(&self, initial: bool) {
+		match self.0.borrow().deref() {
+			CoroutineState::Created(fiber) | CoroutineState::Suspended(fiber) => fiber.touch(initial),
+			CoroutineState::Running | CoroutineState::Finished => (),
+		}
+	}
+	
+	fn heap_size(&self) -> usize {
+		match self.0.borrow().deref() {
+			CoroutineState::Created(fiber) | CoroutineState::Suspended(fiber) => fiber.heap_size(),
+			CoroutineState::Running | CoroutineState::Finished => 0,
+		}
+	}
+}
+
+impl fmt::Debug for Coroutine {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "<coroutine>")
+	}
+}
+
+
+/// An iterator over a range of integers.
+pub struct RangeIterator {
+	next: Cell<i64>,
+	end: i64,
+	inclusive: bool,
+	done: Cell<bool>,
+}
+
+impl RangeIterator {
+	pub fn new(start: i64, end: i64, inclusive: bool) -> RangeIterator {
+		RangeIterator { next: Cell::new(start), end, inclusive, done: Cell::new(false) }
+	}
+}
+
+impl GCIterator for RangeIterator {
+	fn next(&self, vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
+		let i = self.next.get();
+		if self.done.get() || i > self.end || (i == self.end && !self.inclusive) {
+			return Ok(None);
+		}
+		match i.checked_add(1) {
+			Some(next) => self.next.set(next),
+			None => self.done.set(true), // The range ends at i64::MAX
+		}
+		Ok(Some(Value::from_i64(i, vm.heap)))
+	}
+}
+
+
 pub struct VecIterator {
 	pub values: Vec<Value>,
 	pub next_idx: Cell<usize>,
@@ -273,13 +638,13 @@ impl VecIterator {
 }
 
 impl GCIterator for VecIterator {
-	fn next(&mut self, _heap: &mut GCHeap) -> Option<Value> {
+	fn next(&self, _vm: &mut VMHandle) -> Result<Option<Value>, HissyError> {
 		let next_idx = self.next_idx.get();
 		if next_idx < self.values.len() {
 			self.next_idx.set(next_idx + 1);
-			Some(self.values.get(next_idx).unwrap().clone())
+			Ok(Some(self.values.get(next_idx).unwrap().clone()))
 		} else {
-			None
+			Ok(None)
 		}
 	}
 	
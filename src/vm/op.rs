@@ -1,10 +1,48 @@
 use std::convert::TryFrom;
+use std::cmp::Ordering;
 
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::{HissyError, ErrorType};
 use super::value::{Value, ValueType::*};
-use super::gc::GCWrapper;
+use super::gc::{GCWrapper, GCRef, GCHeap};
+
+fn error(s: String) -> HissyError {
+	HissyError::new(ErrorType::Execution, s, 0)
+}
+
+const MAX_INT_BITS: u64 = 1 << 26;
+
+fn get_exponent(exp: &BigInt) -> Result<u32, HissyError> {
+	if exp.is_negative() {
+		return Err(error(format!("Integer power has a negative exponent {}", exp)));
+	}
+	exp.to_u32().ok_or_else(|| error(format!("Integer exponent {} is too large", exp)))
+}
+
+// Fails early if a big integer result of about `bits` bits would be too large,
+// or would not fit under the memory limit
+fn reserve_bits(heap: &mut GCHeap, bits: u64) -> Result<(), HissyError> {
+	if bits > MAX_INT_BITS {
+		return Err(error(format!("Integer result of about {} bits is too large", bits)));
+	}
+	heap.reserve((bits / 8) as usize)
+}
+
+// Lower bound on the size of b^exp in bits
+fn pow_bits(b: &BigInt, exp: u32) -> u64 {
+	b.bits().saturating_sub(1).saturating_mul(u64::from(exp))
+}
+
+fn get_shift(amount: &Value) -> Result<usize, HissyError> {
+	i64::try_from(amount).ok().and_then(|n| usize::try_from(n).ok())
+		.ok_or_else(|| error(format!("Expected a non-negative shift amount, got {}", amount.repr())))
+}
 
 enum NumPair {
-	Ints(i32, i32),
+	Ints(i64, i64),
+	BigInts(BigInt, BigInt), // At least one of them does not fit in an i64
 	Reals(f64, f64),
 	NaN,
 }
@@ -14,6 +52,7 @@ macro_rules! basic_num_op {
 		pub fn $met_name(&self, other: &Value) -> Option<Value> {
 			match self.get_num_pair(other) {
 				NumPair::Ints(i1, i2) => Some(Value::from($fn(i1, i2))),
+				NumPair::BigInts(b1, b2) => Some(Value::from($fn(b1, b2))),
 				NumPair::Reals(r1, r2) => Some(Value::from($fn(r1, r2))),
 				NumPair::NaN => None,
 			}
@@ -21,10 +60,45 @@ macro_rules! basic_num_op {
 	};
 }
 
+// Integer results are computed on i64s, and promoted to big integers on overflow
+macro_rules! int_num_op {
+	($vis:vis $met_name:ident, $int_fn:expr, $big_fn:expr, $real_fn:expr) => {
+		$vis fn $met_name(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+			match self.get_num_pair(other) {
+				NumPair::Ints(i1, i2) => Ok(match $int_fn(i1, i2) {
+					Some(i) => Value::from_i64(i, heap),
+					None => Value::from_bigint($big_fn(BigInt::from(i1), BigInt::from(i2)), heap),
+				}),
+				NumPair::BigInts(b1, b2) => Ok(Value::from_bigint($big_fn(b1, b2), heap)),
+				NumPair::Reals(r1, r2) => Ok(Value::from($real_fn(r1, r2))),
+				NumPair::NaN => Err(error(String::from(concat!("Cannot ", stringify!($met_name), " these values")))),
+			}
+		}
+	};
+}
+
+// Operations only defined on integers, which fail on reals
+macro_rules! int_only_op {
+	($met_name:ident, $int_fn:expr, $big_fn:expr) => {
+		pub fn $met_name(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+			match self.get_num_pair(other) {
+				NumPair::Ints(i1, i2) => Ok(match $int_fn(i1, i2) {
+					Some(i) => Value::from_i64(i, heap),
+					None => Value::from_bigint($big_fn(BigInt::from(i1), BigInt::from(i2)), heap),
+				}),
+				NumPair::BigInts(b1, b2) => Ok(Value::from_bigint($big_fn(b1, b2), heap)),
+				_ => Err(error(String::from(concat!("Cannot ", stringify!($met_name), " these values")))),
+			}
+		}
+	};
+}
+
 /// Provides common operations on `Value`s.
 ///
 /// Notably, numeric `Value`s can be added, substracted, multiplied, divided, `mod`ed, exponentiated, and compared.
 /// The result will have an appropriate numeric type (e.g. int + int = int, int + real = real).
+/// Integer arithmetic never overflows: results are promoted to big integers when needed, and demoted when they fit.
+/// Integers can also be divided with flooring, and combined bitwise (with two's complement semantics).
 /// 
 /// Boolean `Value`s can be 'or'ed, 'and'ed, and 'not'ed.
 ///
@@ -32,68 +106,156 @@ macro_rules! basic_num_op {
 /// 
 /// `Value`s of any type can be compared for equality. Two values of different types will always be unequal,
 /// except for examples such as 2 == 2.0.
+/// 
+/// Numbers and strings also have a natural ordering, used for sorting.
 impl Value {
 	
 	/// Returns whether the `Value` is numeric, ie. contains an integer or real.
 	pub fn is_numeric(&self) -> bool {
-		match self.get_type() {
-			Int | Real => true,
-			_ => false,
-		}
+		self.get_type() == Real || self.is_int()
 	}
 	
 	/// Casts the `Value` to an `f64` if it is numeric. Otherwise, panics.
 	pub fn cast_real(&self) -> f64 {
-		match self.get_type() {
-			Int => i32::try_from(self).unwrap() as f64,
-			Real => f64::try_from(self).unwrap(),
-			_ => panic!("Cannot cast Value to real"),
+		if let Ok(i) = i64::try_from(self) {
+			i as f64
+		} else if let Some(b) = self.to_bigint() {
+			b.to_f64().unwrap()
+		} else {
+			f64::try_from(self).expect("Cannot cast Value to real")
 		}
 	}
 	
 	fn get_num_pair(&self, other: &Value) -> NumPair {
 		if !self.is_numeric() { return NumPair::NaN; }
 		if !other.is_numeric() { return NumPair::NaN; }
-		if self.get_type() == Int && other.get_type() == Int {
-			NumPair::Ints(i32::try_from(self).unwrap(), i32::try_from(other).unwrap())
+		if let (Ok(i1), Ok(i2)) = (i64::try_from(self), i64::try_from(other)) {
+			NumPair::Ints(i1, i2)
+		} else if let (Some(b1), Some(b2)) = (self.to_bigint(), other.to_bigint()) {
+			NumPair::BigInts(b1, b2)
 		} else {
 			NumPair::Reals(self.cast_real(), other.cast_real())
 		}
 	}
 	
-	pub fn neg(&self) -> Option<Value> {
-		match self.get_type() {
-			Int => Some(Value::from(-i32::try_from(self).unwrap())),
-			Real => Some(Value::from(-f64::try_from(self).unwrap())),
-			_ => None,
+	pub fn neg(&self, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		if let Ok(i) = i64::try_from(self) {
+			Ok(match i.checked_neg() {
+				Some(i) => Value::from_i64(i, heap),
+				None => Value::from_bigint(-BigInt::from(i), heap),
+			})
+		} else if let Some(b) = self.to_bigint() {
+			Ok(Value::from_bigint(-b, heap))
+		} else if let Ok(r) = f64::try_from(self) {
+			Ok(Value::from(-r))
+		} else {
+			Err(error(String::from("Cannot negate value!")))
 		}
 	}
 	
-	basic_num_op!(add, |a,b| a + b);
-	basic_num_op!(sub, |a,b| a - b);
-	basic_num_op!(mul, |a,b| a * b);
+	int_num_op!(pub add, i64::checked_add, |a,b| a + b, |a,b| a + b);
+	int_num_op!(pub sub, i64::checked_sub, |a,b| a - b, |a,b| a - b);
+	int_num_op!(pub mul, i64::checked_mul, |a,b| a * b, |a,b| a * b);
+	int_num_op!(modulo_nonzero, i64::checked_rem_euclid, |a: BigInt, b: BigInt| {
+		let r = &a % &b;
+		if r.is_negative() { r + b.abs() } else { r }
+	}, |r1: f64, r2: f64| {
+		let r = r1 % r2;
+		if r < 0.0 { r + r2.abs() } else { r }
+	});
 	
 	pub fn div(&self, other: &Value) -> Option<Value> {
 		if !self.is_numeric() || !other.is_numeric() { return None; }
 		Some(Value::from(self.cast_real() / other.cast_real()))
 	}
 	
-	pub fn pow(&self, other: &Value) -> Option<Value> {
-		if !self.is_numeric() || !other.is_numeric() { return None; }
-		Some(Value::from(self.cast_real().powf(other.cast_real())))
+	/// Raises a number to a power. The result stays an integer if both operands are,
+	/// in which case the exponent must be non-negative: `2 ^ -1` is an error, and
+	/// `2.0 ^ -1` is 0.5.
+	pub fn pow(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		match self.get_num_pair(other) {
+			NumPair::Ints(i1, i2) => {
+				let exp = get_exponent(&BigInt::from(i2))?;
+				Ok(match i1.checked_pow(exp) {
+					Some(i) => Value::from_i64(i, heap),
+					None => {
+						let b1 = BigInt::from(i1);
+						reserve_bits(heap, pow_bits(&b1, exp))?;
+						Value::from_bigint(b1.pow(exp), heap)
+					},
+				})
+			},
+			NumPair::BigInts(b1, b2) => {
+				let exp = get_exponent(&b2)?;
+				reserve_bits(heap, pow_bits(&b1, exp))?;
+				Ok(Value::from_bigint(b1.pow(exp), heap))
+			},
+			NumPair::Reals(r1, r2) => Ok(Value::from(r1.powf(r2))),
+			NumPair::NaN => Err(error(String::from("Cannot pow these values"))),
+		}
 	}
 	
-	pub fn modulo(&self, other: &Value) -> Option<Value> {
-		match self.get_num_pair(other) {
-			NumPair::Ints(i1, i2) => Some(Value::from({
-				let r = i1 % i2;
-				if r < 0 { r + i2.abs() } else { r }
-			})),
-			NumPair::Reals(r1, r2) => Some(Value::from({
-				let r = r1 % r2;
-				if r < 0.0 { r + r2.abs() } else { r }
-			})),
-			NumPair::NaN => None,
+	pub fn modulo(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		if i64::try_from(other) == Ok(0) {
+			return Err(error(String::from("Integer modulo by zero")));
+		}
+		self.modulo_nonzero(other, heap)
+	}
+	
+	int_only_op!(int_div_nonzero, |a: i64, b: i64| a.checked_div(b).map(|q| {
+		if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q }
+	}), |a: BigInt, b: BigInt| {
+		let q = &a / &b;
+		if !(&a % &b).is_zero() && a.is_negative() != b.is_negative() { q - 1 } else { q }
+	});
+	
+	/// Divides two integers, rounding towards negative infinity.
+	pub fn int_div(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		if i64::try_from(other) == Ok(0) {
+			return Err(error(String::from("Integer division by zero")));
+		}
+		self.int_div_nonzero(other, heap)
+	}
+	
+	int_only_op!(bit_and, |a: i64, b: i64| Some(a & b), |a: BigInt, b: BigInt| a & b);
+	int_only_op!(bit_or, |a: i64, b: i64| Some(a | b), |a: BigInt, b: BigInt| a | b);
+	int_only_op!(bit_xor, |a: i64, b: i64| Some(a ^ b), |a: BigInt, b: BigInt| a ^ b);
+	
+	pub fn bit_not(&self, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		if let Ok(i) = i64::try_from(self) {
+			Ok(Value::from_i64(!i, heap))
+		} else if let Some(b) = self.to_bigint() {
+			Ok(Value::from_bigint(!b, heap))
+		} else {
+			Err(error(String::from("Cannot bit_not value")))
+		}
+	}
+	
+	pub fn shl(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		let n = get_shift(other)?;
+		if let Ok(i) = i64::try_from(self) {
+			if n < 64 && (i << n) >> n == i {
+				return Ok(Value::from_i64(i << n, heap));
+			}
+		}
+		match self.to_bigint() {
+			Some(b) => {
+				reserve_bits(heap, b.bits().saturating_add(n as u64))?;
+				Ok(Value::from_bigint(b << n, heap))
+			},
+			None => Err(error(String::from("Cannot shl these values"))),
+		}
+	}
+	
+	/// Shifts an integer right, rounding towards negative infinity.
+	pub fn shr(&self, other: &Value, heap: &mut GCHeap) -> Result<Value, HissyError> {
+		let n = get_shift(other)?;
+		if let Ok(i) = i64::try_from(self) {
+			Ok(Value::from_i64(if n < 64 { i >> n } else if i < 0 { -1 } else { 0 }, heap))
+		} else if let Some(b) = self.to_bigint() {
+			Ok(Value::from_bigint(b >> n, heap))
+		} else {
+			Err(error(String::from("Cannot shr these values")))
 		}
 	}
 	
@@ -125,14 +287,33 @@ impl Value {
 	basic_num_op!(leq, |a,b| a <= b);
 	basic_num_op!(gth, |a,b| a > b);
 	basic_num_op!(geq, |a,b| a >= b);
+	
+	/// Compares two numbers numerically, or two strings lexicographically.
+	/// Returns `None` for any other pair of values, or if a real is NaN.
+	pub fn compare(&self, other: &Value) -> Option<Ordering> {
+		match self.get_num_pair(other) {
+			NumPair::Ints(i1, i2) => Some(i1.cmp(&i2)),
+			NumPair::BigInts(b1, b2) => Some(b1.cmp(&b2)),
+			NumPair::Reals(r1, r2) => r1.partial_cmp(&r2),
+			NumPair::NaN => {
+				let s1 = GCRef::<String>::try_from(self.clone()).ok()?;
+				let s2 = GCRef::<String>::try_from(other.clone()).ok()?;
+				Some(s1.as_str().cmp(s2.as_str()))
+			},
+		}
+	}
 }
 
 impl PartialEq for Value {
 	fn eq(&self, other: &Value) -> bool {
+		if let (Ok(i1), Ok(i2)) = (i64::try_from(self), i64::try_from(other)) {
+			return i1 == i2;
+		} else if self.is_int() && other.is_int() { // Big integers are compared by value
+			return self.to_bigint() == other.to_bigint();
+		}
 		match (self.get_type(), other.get_type()) {
 			(Nil, Nil) => true,
 			(Bool, Bool) => bool::try_from(self).unwrap() == bool::try_from(other).unwrap(),
-			(Int, Int) => i32::try_from(self).unwrap() == i32::try_from(other).unwrap(),
 			(Real, Real) => f64::try_from(self).unwrap() == f64::try_from(other).unwrap(),
 			_ =>
 				if let (Some(p1), Some(p2)) = (self.get_pointer(), other.get_pointer()) {
@@ -143,3 +324,45 @@ impl PartialEq for Value {
 		}
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use crate::ErrorType;
+	use super::super::tests::eval;
+	
+	#[test]
+	fn test_integer_operators() {
+		// Division rounds towards negative infinity, and modulo is never negative
+		assert_eq!(eval("return [7 // 2, (-7) // 2, 7 // (-2), (-7) // (-2)]\n").unwrap(), "[3, -4, -4, 3]");
+		assert_eq!(eval("return [7 % 3, (-7) % 3, 7 % (-3), 7.5 % 2]\n").unwrap(), "[1, 2, 1, 1.5]");
+		assert_eq!(eval("let b = 2 ^ 70\nreturn [b // -3, (0 - b) % 3]\n").unwrap(), "[-393530540239137101142, 2]");
+		
+		assert_eq!(eval("return [1 << 4, (-16) >> 2, (-1) >> 70, 5 >> 1, 1 << 64, (1 << 64) >> 63]\n").unwrap(),
+			"[16, -4, -1, 2, 18446744073709551616, 2]");
+		assert_eq!(eval("return [6 & 3, 6 | 3, 6 ~ 3, ~5, ~(0 - 1), ((1 << 70) | 1) & 3]\n").unwrap(), "[2, 7, 5, -6, 0, 1]");
+		
+		assert_eq!(eval("return 1 // 0\n").unwrap_err().1, "Integer division by zero");
+		assert_eq!(eval("return 1 % 0\n").unwrap_err().1, "Integer modulo by zero");
+		assert_eq!(eval("return 1 << (0 - 1)\n").unwrap_err().1, "Expected a non-negative shift amount, got -1");
+		for code in ["return 1.5 // 2\n", "return 1 & 2.0\n", "return ~1.5\n", "return 1 << 2.0\n"].iter() {
+			assert!(matches!(eval(code).unwrap_err().0, ErrorType::Compilation), "{}", code);
+		}
+	}
+	
+	#[test]
+	fn test_int_pow() {
+		assert_eq!(eval("return 2 ^ 10\n").unwrap(), "1024");
+		assert_eq!(eval("return 2 ^ 100\n").unwrap(), "1267650600228229401496703205376");
+		assert_eq!(eval("return [2.0 ^ 3, 4.0 ^ (0 - 1), 4 ^ 0.5]\n").unwrap(), "[8.0, 0.25, 2.0]");
+		
+		// Powers of integers are integers, even with a variable exponent, which must not be negative
+		assert_eq!(eval("let n = 10\nlet i: Int = 2 ^ n\nreturn i\n").unwrap(), "1024");
+		assert_eq!(eval("let n = 0 - 2\nreturn 4 ^ n\n").unwrap_err().1, "Integer power has a negative exponent -2");
+		assert_eq!(eval("let n = 0 - 2 ^ 70\nreturn 4 ^ n\n").unwrap_err().1,
+			"Integer power has a negative exponent -1180591620717411303424");
+		
+		let err = eval("return 2 ^ 4000000000\n").unwrap_err();
+		assert_eq!(err.1, "Integer result of about 4000000000 bits is too large");
+	}
+}
@@ -1,121 +1,1041 @@
 
 use std::convert::TryFrom;
-use std::cell::RefCell;
 use std::iter::Iterator;
+use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::env;
+
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
 
 use crate::{prim_ty, HissyError, ErrorType};
 use crate::compiler::{Type, PrimitiveType};
+use crate::vm::{VMHandle, FromValue};
 use crate::vm::gc::{GCHeap, GCRef};
 use crate::vm::value::{Value, NIL};
-use crate::vm::object::{NativeFunction, List, Namespace, IteratorWrapper, VecIterator};
+use crate::vm::object::{NativeFunction, Intrinsic, List, Map, Namespace, IteratorWrapper, VecIterator, RangeIterator, Coroutine,
+	resolve_index, slice_bounds};
+use crate::vm::adaptors::{self, call_predicate, get_count};
+use crate::vm::random::Rng;
+use crate::vm::console::io_error;
+use crate::vm::json;
+use crate::vm::clock::format_time;
 
 fn error(s: String) -> HissyError {
-	HissyError(ErrorType::Execution, s, 0)
+	HissyError::new(ErrorType::Execution, s, 0)
+}
+
+fn get_index(val: &Value, len: usize) -> Result<usize, HissyError> {
+	let idx = i64::try_from(val).map_err(|_| error(format!("Expected integer index, got {}", val.repr())))?;
+	resolve_index(idx, len).ok_or_else(|| error(format!("Index {} is out of bounds for list of length {}", idx, len)))
+}
+
+// Numbers and strings are compared by value, other objects by identity
+fn values_equal(a: &Value, b: &Value) -> bool {
+	a == b || a.compare(b) == Some(Ordering::Equal)
+}
+
+fn check_arg_count(args: &[Value], n: usize) -> Result<(), HissyError> {
+	if args.len() == n {
+		Ok(())
+	} else {
+		Err(error(format!("Expected {} argument{}, got {}", n, if n == 1 { "" } else { "s" }, args.len())))
+	}
+}
+
+fn get_real(val: &Value) -> Result<f64, HissyError> {
+	if val.is_numeric() {
+		Ok(val.cast_real())
+	} else {
+		Err(error(format!("Expected a number, got {}", val.repr())))
+	}
+}
+
+// Truncates a real towards zero
+fn real_to_int(r: f64, heap: &mut GCHeap) -> Result<Value, HissyError> {
+	if !r.is_finite() {
+		return Err(error(format!("Cannot convert {} to an integer", r)));
+	}
+	let r = r.trunc();
+	if r >= i64::MIN as f64 && r < i64::MAX as f64 {
+		Ok(Value::from_i64(r as i64, heap))
+	} else {
+		Ok(Value::from_bigint(BigInt::from_f64(r).unwrap(), heap))
+	}
+}
+
+fn parse_int(s: &str, heap: &mut GCHeap) -> Option<Value> {
+	s.trim().parse::<BigInt>().ok().map(|b| Value::from_bigint(b, heap))
+}
+
+fn parse_real(s: &str) -> Option<Value> {
+	s.trim().parse::<f64>().ok().map(Value::from)
+}
+
+// Rounds a number to an integer with the given function, leaving integers unchanged
+fn round_with(vm: &mut VMHandle, args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value, HissyError> {
+	check_arg_count(&args, 1)?;
+	if args[0].is_int() {
+		Ok(args[0].clone())
+	} else {
+		real_to_int(f(get_real(&args[0])?), vm.heap)
+	}
+}
+
+fn real_fn(args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value, HissyError> {
+	check_arg_count(&args, 1)?;
+	Ok(Value::from(f(get_real(&args[0])?)))
+}
+
+// Returns the argument which compares as `ord` to the other one
+fn pick_by_order(args: Vec<Value>, ord: Ordering) -> Result<Value, HissyError> {
+	check_arg_count(&args, 2)?;
+	match args[0].compare(&args[1]) {
+		Some(o) if o == ord => Ok(args[0].clone()),
+		Some(_) => Ok(args[1].clone()),
+		None => Err(error(format!("Cannot compare {} and {}", args[0].repr(), args[1].repr()))),
+	}
+}
+
+fn get_string(val: &Value) -> Result<String, HissyError> {
+	GCRef::<String>::try_from(val.clone()).map(|s| s.as_str().to_owned())
+		.map_err(|_| error(format!("Expected a string, got {}", val.repr())))
+}
+
+// File system failures do not abort the script: the operation returns `on_failure` instead (nil, false or
+// an empty list), which scripts must check, and `fs.last_error` describes the failure until the next operation
+// succeeds. Only calling `fs` functions while file system access is disabled is an error.
+fn fs_op<T>(vm: &mut VMHandle, path: &str, op: impl FnOnce() -> io::Result<T>, on_failure: T) -> Result<T, HissyError> {
+	let last_error = vm.fs_error()?;
+	match op() {
+		Ok(res) => {
+			last_error.clear();
+			Ok(res)
+		},
+		Err(err) => {
+			*last_error = format!("{}: {}", path, err);
+			Ok(on_failure)
+		},
+	}
+}
+
+// Strings are printed as is, other values through their representation
+fn display_string(val: &Value) -> String {
+	match GCRef::<String>::try_from(val.clone()) {
+		Ok(s) => s.as_str().to_owned(),
+		Err(_) => val.repr(),
+	}
+}
+
+fn make_list(heap: &mut GCHeap, values: &[Value]) -> Result<Value, HissyError> {
+	let list = List::new();
+	list.extend(values);
+	heap.try_make_value(list)
+}
+
+// Adds a value to a list being built by a native, failing as soon as it outgrows the memory limit
+fn push_checked(heap: &mut GCHeap, list: &GCRef<List>, val: Value) -> Result<(), HissyError> {
+	list.extend(&[ val ]);
+	heap.update_size(list)
+}
+
+// Stable merge sort, since the comparison function may fail or be inconsistent
+fn sort_by(vm: &mut VMHandle, values: Vec<Value>, cmp: &Value) -> Result<Vec<Value>, HissyError> {
+	if values.len() <= 1 {
+		return Ok(values);
+	}
+	let mut left = values;
+	let right = left.split_off(left.len() / 2);
+	let mut left = VecDeque::from(sort_by(vm, left, cmp)?);
+	let mut right = VecDeque::from(sort_by(vm, right, cmp)?);
+	let mut res = Vec::with_capacity(left.len() + right.len());
+	while let (Some(a), Some(b)) = (left.front(), right.front()) {
+		let ord = vm.call(cmp, vec![a.clone(), b.clone()])?;
+		let ord = i64::try_from(&ord)
+			.map_err(|_| error(format!("Expected comparison function to return an integer, got {}", ord.repr())))?;
+		if ord > 0 {
+			res.push(right.pop_front().unwrap());
+		} else {
+			res.push(left.pop_front().unwrap());
+		}
+	}
+	res.extend(left);
+	res.extend(right);
+	Ok(res)
 }
 
 pub fn list() -> Vec<(String, Type)> {
+	// Generic method signatures: T is the element type of the receiver, U is bound by the arguments
+	let t = || Box::new(Type::Param(0));
+	let u = || Box::new(Type::Param(1));
 	vec![
 		(String::from("List"), Type::Namespace(vec![
 			(String::from("size"), Type::TypedFunction(vec![], Box::new(prim_ty!(Int)))),
-			(String::from("add"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Nil)))),
-			(String::from("iter"), Type::TypedFunction(vec![], Box::new(Type::Iterator(Box::new(Type::Any))))),
+			(String::from("add"), Type::TypedFunction(vec![Type::Param(0)], Box::new(prim_ty!(Nil)))),
+			(String::from("iter"), Type::TypedFunction(vec![], Box::new(Type::Iterator(t())))),
+			(String::from("map"), Type::TypedFunction(vec![Type::UntypedFunction(u())], Box::new(Type::List(u())))),
+			(String::from("filter"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(prim_ty!(Bool)))], Box::new(Type::List(t())))),
+			(String::from("for_each"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(Type::Any))], Box::new(prim_ty!(Nil)))),
+			(String::from("sort_by"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(prim_ty!(Int)))], Box::new(prim_ty!(Nil)))),
+			(String::from("pop"), Type::TypedFunction(vec![], t())),
+			(String::from("insert"), Type::TypedFunction(vec![prim_ty!(Int), Type::Param(0)], Box::new(prim_ty!(Nil)))),
+			(String::from("remove_at"), Type::TypedFunction(vec![prim_ty!(Int)], t())),
+			(String::from("clear"), Type::TypedFunction(vec![], Box::new(prim_ty!(Nil)))),
+			(String::from("contains"), Type::TypedFunction(vec![Type::Param(0)], Box::new(prim_ty!(Bool)))),
+			(String::from("index_of"), Type::TypedFunction(vec![Type::Param(0)], Box::new(prim_ty!(Int)))),
+			(String::from("reverse"), Type::TypedFunction(vec![], Box::new(prim_ty!(Nil)))),
+			(String::from("sort"), Type::TypedFunction(vec![], Box::new(prim_ty!(Nil)))),
+			(String::from("slice"), Type::TypedFunction(vec![prim_ty!(Int), prim_ty!(Int)], Box::new(Type::List(t())))),
+			(String::from("concat"), Type::TypedFunction(vec![Type::List(t())], Box::new(Type::List(t())))),
+			(String::from("extend"), Type::TypedFunction(vec![Type::List(t())], Box::new(prim_ty!(Nil)))),
+			(String::from("copy"), Type::TypedFunction(vec![], Box::new(Type::List(t())))),
+			(String::from("join"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(prim_ty!(String)))),
 		])),
 		(String::from("Iterator"), Type::Namespace(vec![
-			(String::from("next"), Type::TypedFunction(vec![], Box::new(Type::Any))),
+			(String::from("next"), Type::TypedFunction(vec![], t())),
+			(String::from("for_each"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(Type::Any))], Box::new(prim_ty!(Nil)))),
+			(String::from("map"), Type::TypedFunction(vec![Type::UntypedFunction(u())], Box::new(Type::Iterator(u())))),
+			(String::from("filter"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(prim_ty!(Bool)))], Box::new(Type::Iterator(t())))),
+			(String::from("enumerate"), Type::TypedFunction(vec![], Box::new(Type::Iterator(Box::new(Type::List(Box::new(Type::Any))))))),
+			(String::from("zip"), Type::TypedFunction(vec![Type::Iterator(Box::new(Type::Any))], Box::new(Type::Iterator(Box::new(Type::List(Box::new(Type::Any))))))),
+			(String::from("take"), Type::TypedFunction(vec![prim_ty!(Int)], Box::new(Type::Iterator(t())))),
+			(String::from("skip"), Type::TypedFunction(vec![prim_ty!(Int)], Box::new(Type::Iterator(t())))),
+			(String::from("chain"), Type::TypedFunction(vec![Type::Iterator(t())], Box::new(Type::Iterator(t())))),
+			(String::from("take_while"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(prim_ty!(Bool)))], Box::new(Type::Iterator(t())))),
+			(String::from("collect"), Type::TypedFunction(vec![], Box::new(Type::List(t())))),
+			(String::from("count"), Type::TypedFunction(vec![], Box::new(prim_ty!(Int)))),
+			(String::from("sum"), Type::TypedFunction(vec![], t())),
+			(String::from("any"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(prim_ty!(Bool)))], Box::new(prim_ty!(Bool)))),
+			(String::from("all"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(prim_ty!(Bool)))], Box::new(prim_ty!(Bool)))),
+			(String::from("fold"), Type::TypedFunction(vec![Type::Param(1), Type::UntypedFunction(u())], u())),
+		])),
+		(String::from("Map"), Type::Namespace(vec![
+			(String::from("size"), Type::TypedFunction(vec![], Box::new(prim_ty!(Int)))),
+			(String::from("get"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::Any))),
+			(String::from("has"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(prim_ty!(Bool)))),
+			(String::from("keys"), Type::TypedFunction(vec![], Box::new(Type::List(Box::new(prim_ty!(String)))))),
 		])),
 		(String::from("log"), Type::UntypedFunction(Box::new(prim_ty!(Nil)))),
+		(String::from("print"), Type::UntypedFunction(Box::new(prim_ty!(Nil)))),
+		(String::from("input"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(prim_ty!(String)))),
 		(String::from("range"), Type::TypedFunction(vec![prim_ty!(Int), prim_ty!(Int)], Box::new(Type::Iterator(Box::new(prim_ty!(Int)))))),
 		(String::from("int"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Int)))),
+		(String::from("real"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
 		(String::from("string"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(String)))),
+		(String::from("map"), Type::TypedFunction(vec![Type::Any], Box::new(Type::Map))),
+		(String::from("list"), Type::TypedFunction(vec![Type::Any], Box::new(Type::List(Box::new(Type::Any))))),
+		(String::from("parse_int"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::Any))),
+		(String::from("parse_real"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::Any))),
+		(String::from("Coroutine"), Type::Namespace(vec![
+			(String::from("resume"), Type::UntypedFunction(Box::new(Type::Any))),
+			(String::from("status"), Type::TypedFunction(vec![], Box::new(prim_ty!(String)))),
+		])),
+		(String::from("coroutine"), Type::TypedFunction(vec![Type::UntypedFunction(Box::new(Type::Any))], Box::new(Type::Coroutine))),
+		(String::from("suspend"), Type::TypedFunction(vec![Type::Any], Box::new(Type::Any))),
+		(String::from("args"), Type::List(Box::new(prim_ty!(String)))),
+		(String::from("env"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::Any))),
+		(String::from("exit"), Type::TypedFunction(vec![prim_ty!(Int)], Box::new(prim_ty!(Nil)))),
+		(String::from("math"), Type::Module(vec![
+			(String::from("floor"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Int)))),
+			(String::from("ceil"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Int)))),
+			(String::from("round"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Int)))),
+			(String::from("abs"), Type::TypedFunction(vec![Type::Param(0)], Box::new(Type::Param(0)))),
+			(String::from("sqrt"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
+			(String::from("sin"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
+			(String::from("cos"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
+			(String::from("tan"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
+			(String::from("log"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
+			(String::from("exp"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Real)))),
+			(String::from("min"), Type::TypedFunction(vec![Type::Param(0), Type::Param(0)], Box::new(Type::Param(0)))),
+			(String::from("max"), Type::TypedFunction(vec![Type::Param(0), Type::Param(0)], Box::new(Type::Param(0)))),
+			(String::from("pi"), prim_ty!(Real)),
+		])),
+		(String::from("random"), Type::Module(vec![
+			(String::from("seed"), Type::TypedFunction(vec![prim_ty!(Int)], Box::new(prim_ty!(Nil)))),
+			(String::from("int"), Type::TypedFunction(vec![prim_ty!(Int), prim_ty!(Int)], Box::new(prim_ty!(Int)))),
+			(String::from("real"), Type::TypedFunction(vec![], Box::new(prim_ty!(Real)))),
+			(String::from("choice"), Type::TypedFunction(vec![Type::List(Box::new(Type::Param(0)))], Box::new(Type::Param(0)))),
+			(String::from("shuffle"), Type::TypedFunction(vec![Type::List(Box::new(Type::Param(0)))], Box::new(prim_ty!(Nil)))),
+		])),
+		(String::from("fs"), Type::Module(vec![
+			(String::from("read_text"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::Any))),
+			(String::from("write_text"), Type::TypedFunction(vec![prim_ty!(String), prim_ty!(String)], Box::new(prim_ty!(Bool)))),
+			(String::from("append_text"), Type::TypedFunction(vec![prim_ty!(String), prim_ty!(String)], Box::new(prim_ty!(Bool)))),
+			(String::from("exists"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(prim_ty!(Bool)))),
+			(String::from("list_dir"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::List(Box::new(prim_ty!(String)))))),
+			(String::from("remove"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(prim_ty!(Bool)))),
+			(String::from("mkdir"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(prim_ty!(Bool)))),
+			(String::from("last_error"), Type::TypedFunction(vec![], Box::new(prim_ty!(String)))),
+		])),
+		(String::from("json"), Type::Module(vec![
+			(String::from("parse"), Type::TypedFunction(vec![prim_ty!(String)], Box::new(Type::Any))),
+			(String::from("stringify"), Type::TypedFunction(vec![Type::Any, prim_ty!(Int)], Box::new(prim_ty!(String)))),
+		])),
+		(String::from("time"), Type::Module(vec![
+			(String::from("now"), Type::TypedFunction(vec![], Box::new(prim_ty!(Real)))),
+			(String::from("wall"), Type::TypedFunction(vec![], Box::new(prim_ty!(Real)))),
+			(String::from("sleep"), Type::TypedFunction(vec![Type::Any], Box::new(prim_ty!(Nil)))),
+			(String::from("format"), Type::TypedFunction(vec![Type::Any, prim_ty!(String)], Box::new(prim_ty!(String)))),
+		])),
 	]
 }
 
-pub fn create(heap: &mut GCHeap) -> Vec<Value> {
+// Creates the values of the prelude, in the order of `list`, along with the `args` list
+pub fn create(heap: &mut GCHeap) -> (Vec<Value>, GCRef<List>) {
 	let mut res = vec![];
 	
-	let list_size = heap.make_value(NativeFunction::new(|_heap, args| {
+	let list_size = heap.make_value(NativeFunction::new(|vm, args| {
 		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
-		Ok(Value::from(this.len() as i32))
+		Ok(Value::from_i64(this.len() as i64, vm.heap))
 	}));
-	let list_add = heap.make_value(NativeFunction::new(|_heap, args| {
+	let list_add = heap.make_value(NativeFunction::new(|vm, args| {
 		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
-		this.extend(&[ args[1].clone() ]);
+		push_checked(vm.heap, &this, args[1].clone())?;
 		Ok(NIL)
 	}));
-	let list_iter = heap.make_value(NativeFunction::new(|heap, args| {
+	let list_iter = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		Ok(vm.heap.make_value(IteratorWrapper::new(VecIterator::new(this.get_copy()))))
+	}));
+	let list_map = heap.make_value(NativeFunction::new(|vm, args| {
 		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
-		Ok(heap.make_value(IteratorWrapper {
-			iter: Box::new(RefCell::new(
-				VecIterator::new(this.get_copy())
-			))
-		}))
+		let mapped = vm.heap.make_ref(List::new());
+		for val in this.get_copy() {
+			let val = vm.call(&args[1], vec![val])?;
+			push_checked(vm.heap, &mapped, val)?;
+		}
+		Ok(Value::from(mapped))
+	}));
+	let list_filter = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let kept = vm.heap.make_ref(List::new());
+		for val in this.get_copy() {
+			if call_predicate(vm, &args[1], vec![val.clone()])? {
+				push_checked(vm.heap, &kept, val)?;
+			}
+		}
+		Ok(Value::from(kept))
+	}));
+	let list_for_each = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		for val in this.get_copy() {
+			vm.call(&args[1], vec![val])?;
+		}
+		Ok(NIL)
+	}));
+	let list_sort_by = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let sorted = sort_by(vm, this.get_copy(), &args[1])?;
+		this.replace(sorted);
+		Ok(NIL)
+	}));
+	let list_pop = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let val = this.pop().ok_or_else(|| error(String::from("Cannot pop from empty list")))?;
+		vm.heap.update_size(&this)?;
+		Ok(val)
+	}));
+	let list_insert = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let idx = get_index(&args[1], this.len())?;
+		this.insert(idx, args[2].clone())?;
+		vm.heap.update_size(&this)?;
+		Ok(NIL)
+	}));
+	let list_remove_at = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let idx = get_index(&args[1], this.len())?;
+		let val = this.remove(idx)?;
+		vm.heap.update_size(&this)?;
+		Ok(val)
+	}));
+	let list_clear = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		this.clear();
+		vm.heap.update_size(&this)?;
+		Ok(NIL)
+	}));
+	let list_contains = heap.make_value(NativeFunction::new(|_vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		Ok(Value::from(this.get_copy().iter().any(|val| values_equal(val, &args[1]))))
+	}));
+	let list_index_of = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let idx = this.get_copy().iter().position(|val| values_equal(val, &args[1]));
+		Ok(Value::from_i64(idx.map_or(-1, |idx| idx as i64), vm.heap))
+	}));
+	let list_reverse = heap.make_value(NativeFunction::new(|_vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let mut values = this.get_copy();
+		values.reverse();
+		this.replace(values);
+		Ok(NIL)
+	}));
+	let list_sort = heap.make_value(NativeFunction::new(|_vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let mut values = this.get_copy();
+		let mut err = None;
+		values.sort_by(|a, b| {
+			a.compare(b).unwrap_or_else(|| {
+				err.get_or_insert_with(|| error(format!("Cannot compare {} and {}", a.repr(), b.repr())));
+				Ordering::Equal
+			})
+		});
+		if let Some(err) = err {
+			return Err(err);
+		}
+		this.replace(values);
+		Ok(NIL)
+	}));
+	let list_slice = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let values = this.get_copy();
+		let (start, end) = slice_bounds(&args[1], &args[2], values.len())?;
+		make_list(vm.heap, &values[start..end])
+	}));
+	let list_concat = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let other = GCRef::<List>::try_from(args[1].clone()).unwrap();
+		let mut values = this.get_copy();
+		values.extend(other.get_copy());
+		make_list(vm.heap, &values)
+	}));
+	let list_extend = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let other = GCRef::<List>::try_from(args[1].clone()).unwrap();
+		this.extend(&other.get_copy());
+		vm.heap.update_size(&this)?;
+		Ok(NIL)
+	}));
+	let list_copy = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		make_list(vm.heap, &this.get_copy())
+	}));
+	let list_join = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let sep = GCRef::<String>::try_from(args[1].clone()).unwrap();
+		let mut joined = String::new();
+		for (i, val) in this.get_copy().iter().enumerate() {
+			if i > 0 {
+				joined.push_str(&sep);
+			}
+			joined.push_str(&display_string(val));
+			vm.heap.reserve(joined.capacity())?;
+		}
+		vm.heap.try_make_value(joined)
 	}));
 	res.push(heap.make_value(
-		Namespace(vec![ list_size, list_add, list_iter ])
+		Namespace(vec![ list_size, list_add, list_iter, list_map, list_filter, list_for_each, list_sort_by,
+			list_pop, list_insert, list_remove_at, list_clear, list_contains, list_index_of, list_reverse, list_sort,
+			list_slice, list_concat, list_extend, list_copy, list_join ])
 	));
 	
-	let iter_next = heap.make_value(NativeFunction::new(|heap, args| {
-		let this = GCRef::<IteratorWrapper>::try_from(args[0].clone()).unwrap();
-		Ok(this.next(heap).unwrap_or(NIL))
+	let iter_next = heap.make_value(Intrinsic::IterNext);
+	let iter_for_each = heap.make_value(NativeFunction::new(|vm, args| {
+		while let Some(val) = vm.next(&args[0])? {
+			vm.call(&args[1], vec![val])?;
+		}
+		Ok(NIL)
+	}));
+	let iter_map = heap.make_value(NativeFunction::new(|vm, args| {
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Map::new(args[0].clone(), args[1].clone()))))
+	}));
+	let iter_filter = heap.make_value(NativeFunction::new(|vm, args| {
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Filter::new(args[0].clone(), args[1].clone()))))
+	}));
+	let iter_enumerate = heap.make_value(NativeFunction::new(|vm, args| {
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Enumerate::new(args[0].clone()))))
+	}));
+	let iter_zip = heap.make_value(NativeFunction::new(|vm, args| {
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Zip::new(args[0].clone(), args[1].clone()))))
+	}));
+	let iter_take = heap.make_value(NativeFunction::new(|vm, args| {
+		let n = get_count(&args[1])?;
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Take::new(args[0].clone(), n))))
+	}));
+	let iter_skip = heap.make_value(NativeFunction::new(|vm, args| {
+		let n = get_count(&args[1])?;
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Skip::new(args[0].clone(), n))))
+	}));
+	let iter_chain = heap.make_value(NativeFunction::new(|vm, args| {
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::Chain::new(args[0].clone(), args[1].clone()))))
+	}));
+	let iter_take_while = heap.make_value(NativeFunction::new(|vm, args| {
+		Ok(vm.heap.make_value(IteratorWrapper::new(adaptors::TakeWhile::new(args[0].clone(), args[1].clone()))))
+	}));
+	let iter_collect = heap.make_value(NativeFunction::new(|vm, args| {
+		let list = vm.heap.make_ref(List::new());
+		while let Some(val) = vm.next(&args[0])? {
+			push_checked(vm.heap, &list, val)?;
+		}
+		Ok(Value::from(list))
+	}));
+	let iter_count = heap.make_value(NativeFunction::new(|vm, args| {
+		let mut count = 0;
+		while vm.next(&args[0])?.is_some() {
+			count += 1;
+		}
+		Ok(Value::from(count))
+	}));
+	let iter_sum = heap.make_value(NativeFunction::new(|vm, args| {
+		let mut sum = match vm.next(&args[0])? {
+			Some(val) => val,
+			None => return Ok(Value::from(0)),
+		};
+		while let Some(val) = vm.next(&args[0])? {
+			sum = sum.add(&val, vm.heap)?;
+		}
+		Ok(sum)
+	}));
+	let iter_any = heap.make_value(NativeFunction::new(|vm, args| {
+		while let Some(val) = vm.next(&args[0])? {
+			if call_predicate(vm, &args[1], vec![val])? {
+				return Ok(Value::from(true));
+			}
+		}
+		Ok(Value::from(false))
+	}));
+	let iter_all = heap.make_value(NativeFunction::new(|vm, args| {
+		while let Some(val) = vm.next(&args[0])? {
+			if !call_predicate(vm, &args[1], vec![val])? {
+				return Ok(Value::from(false));
+			}
+		}
+		Ok(Value::from(true))
+	}));
+	let iter_fold = heap.make_value(NativeFunction::new(|vm, args| {
+		let mut acc = args[1].clone();
+		while let Some(val) = vm.next(&args[0])? {
+			acc = vm.call(&args[2], vec![acc, val])?;
+		}
+		Ok(acc)
 	}));
 	res.push(heap.make_value(
-		Namespace(vec![ iter_next ])
+		Namespace(vec![ iter_next, iter_for_each, iter_map, iter_filter, iter_enumerate, iter_zip, iter_take, iter_skip,
+			iter_chain, iter_take_while, iter_collect, iter_count, iter_sum, iter_any, iter_all, iter_fold ])
 	));
 	
+	let map_size = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<Map>::try_from(args[0].clone()).unwrap();
+		Ok(Value::from_i64(this.len() as i64, vm.heap))
+	}));
+	let map_get = heap.make_value(NativeFunction::new(|_vm, args| {
+		let this = GCRef::<Map>::try_from(args[0].clone()).unwrap();
+		Ok(this.get(&get_string(&args[1])?).unwrap_or(NIL))
+	}));
+	let map_has = heap.make_value(NativeFunction::new(|_vm, args| {
+		let this = GCRef::<Map>::try_from(args[0].clone()).unwrap();
+		Ok(Value::from(this.contains_key(&get_string(&args[1])?)))
+	}));
+	let map_keys = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<Map>::try_from(args[0].clone()).unwrap();
+		let keys = vm.heap.make_ref(List::new());
+		for (key, _) in this.get_entries() {
+			let key = vm.heap.try_make_value(key)?;
+			push_checked(vm.heap, &keys, key)?;
+		}
+		Ok(Value::from(keys))
+	}));
+	res.push(heap.make_value(
+		Namespace(vec![ map_size, map_get, map_has, map_keys ])
+	));
+	
+	res.push(heap.make_value(
+		NativeFunction::new(|vm, args| {
+			let line: Vec<String> = args.iter().map(Value::repr).collect();
+			writeln!(vm.output(), "{}", line.join(" ")).map_err(io_error)?;
+			Ok(NIL)
+		})
+	));
+	res.push(heap.make_value(
+		NativeFunction::new(|vm, args| {
+			let text: Vec<String> = args.iter().map(display_string).collect();
+			let out = vm.output();
+			write!(out, "{}", text.join(" ")).map_err(io_error)?;
+			out.flush().map_err(io_error)?;
+			Ok(NIL)
+		})
+	));
 	res.push(heap.make_value(
-		NativeFunction::new(|_heap, args| {
-			let mut it = args.iter();
-			if let Some(val) = it.next() {
-				print!("{}", val.repr());
-				for val in it {
-					print!(" {}", val.repr());
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 1)?;
+			let out = vm.output();
+			write!(out, "{}", display_string(&args[0])).map_err(io_error)?;
+			out.flush().map_err(io_error)?;
+			let mut line = String::new();
+			if vm.input().read_line(&mut line).map_err(io_error)? == 0 {
+				return Err(HissyError::new(ErrorType::IO, String::from("Unexpected end of input"), 0));
+			}
+			if line.ends_with('\n') {
+				line.pop();
+				if line.ends_with('\r') {
+					line.pop();
 				}
 			}
-			println!();
-			Ok(NIL)
+			Ok(vm.heap.make_value(line))
 		})
 	));
 	
 	res.push(heap.make_value(
-		NativeFunction::new(|heap, args| {
-			if args.len() != 2 {
-				return Err(error(format!("Expected 2 arguments, got {}", args.len())));
-			}
-			let start = i32::try_from(&args[0]).unwrap();
-			let end = i32::try_from(&args[1]).unwrap();
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 2)?;
+			let start = i64::from_value(&args[0])?;
+			let end = i64::from_value(&args[1])?;
 			
-			Ok(heap.make_value(IteratorWrapper {
-				iter: Box::new(RefCell::new(
-					(start..end).map(Value::from)
-				))
-			}))
+			Ok(vm.heap.make_value(IteratorWrapper::new(RangeIterator::new(start, end, false))))
 		})
 	));
 	
 	res.push(heap.make_value(
-		NativeFunction::new(|_heap, args| {
-			if args.len() != 1 {
-				return Err(error(format!("Expected 1 argument, got {}", args.len())));
-			}
-			if i32::try_from(&args[0]).is_ok() {
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 1)?;
+			if args[0].is_int() {
 				Ok(args[0].clone())
+			} else if let Ok(r) = f64::try_from(&args[0]) {
+				real_to_int(r, vm.heap)
+			} else if let Ok(s) = GCRef::<String>::try_from(args[0].clone()) {
+				parse_int(&s, vm.heap).ok_or_else(|| error(format!("Cannot parse {:?} as an integer", s.as_str())))
 			} else {
-				Err(error(format!("Expected integer value, got {:?}", &args[0])))
+				Err(error(format!("Cannot convert {} to an integer", args[0].repr())))
 			}
 		})
 	));
 	res.push(heap.make_value(
-		NativeFunction::new(|_heap, args| {
-			if args.len() != 1 {
-				return Err(error(format!("Expected 1 argument, got {}", args.len())));
+		NativeFunction::new(|_vm, args| {
+			check_arg_count(&args, 1)?;
+			if args[0].is_numeric() {
+				Ok(Value::from(args[0].cast_real()))
+			} else if let Ok(s) = GCRef::<String>::try_from(args[0].clone()) {
+				parse_real(&s).ok_or_else(|| error(format!("Cannot parse {:?} as a real", s.as_str())))
+			} else {
+				Err(error(format!("Cannot convert {} to a real", args[0].repr())))
 			}
+		})
+	));
+	res.push(heap.make_value(
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 1)?;
 			if GCRef::<String>::try_from(args[0].clone()).is_ok() {
 				Ok(args[0].clone())
 			} else {
-				Err(error(format!("Expected string value, got {:?}", &args[0])))
+				vm.heap.try_make_value(args[0].repr())
 			}
 		})
 	));
+	res.push(heap.make_value(
+		NativeFunction::new(|_vm, args| {
+			check_arg_count(&args, 1)?;
+			if GCRef::<Map>::try_from(args[0].clone()).is_ok() {
+				Ok(args[0].clone())
+			} else {
+				Err(error(format!("Cannot convert {} to a map", args[0].repr())))
+			}
+		})
+	));
+	res.push(heap.make_value(
+		NativeFunction::new(|_vm, args| {
+			check_arg_count(&args, 1)?;
+			if GCRef::<List>::try_from(args[0].clone()).is_ok() {
+				Ok(args[0].clone())
+			} else {
+				Err(error(format!("Cannot convert {} to a list", args[0].repr())))
+			}
+		})
+	));
+	res.push(heap.make_value(
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 1)?;
+			let s = GCRef::<String>::try_from(args[0].clone()).unwrap();
+			Ok(parse_int(&s, vm.heap).unwrap_or(NIL))
+		})
+	));
+	res.push(heap.make_value(
+		NativeFunction::new(|_vm, args| {
+			check_arg_count(&args, 1)?;
+			let s = GCRef::<String>::try_from(args[0].clone()).unwrap();
+			Ok(parse_real(&s).unwrap_or(NIL))
+		})
+	));
+	
+	let co_resume = heap.make_value(Intrinsic::Resume);
+	let co_status = heap.make_value(NativeFunction::new(|vm, args| {
+		let this = GCRef::<Coroutine>::try_from(args[0].clone()).unwrap();
+		Ok(vm.heap.make_value(String::from(this.status().name())))
+	}));
+	res.push(heap.make_value(
+		Namespace(vec![ co_resume, co_status ])
+	));
+	res.push(heap.make_value(Intrinsic::MakeCoroutine));
+	res.push(heap.make_value(Intrinsic::Suspend));
+	
+	let script_args = heap.make_ref(List::new()); // Filled by the host with VM::set_args
+	res.push(Value::from(script_args.clone()));
+	res.push(heap.make_value(
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 1)?;
+			let name = get_string(&args[0])?;
+			match env::var(&name) {
+				Ok(val) => vm.heap.try_make_value(val),
+				Err(_) => Ok(NIL), // Unset or not Unicode
+			}
+		})
+	));
+	res.push(heap.make_value(
+		NativeFunction::new(|vm, args| {
+			check_arg_count(&args, 1)?;
+			let code = i32::try_from(&args[0])
+				.map_err(|_| error(format!("Exit code {} does not fit in 32 bits", args[0].repr())))?;
+			Err(vm.exit(code))
+		})
+	));
+	
+	let math_floor = heap.make_value(NativeFunction::new(|vm, args| round_with(vm, args, f64::floor)));
+	let math_ceil = heap.make_value(NativeFunction::new(|vm, args| round_with(vm, args, f64::ceil)));
+	let math_round = heap.make_value(NativeFunction::new(|vm, args| round_with(vm, args, f64::round)));
+	let math_abs = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		if args[0].is_int() {
+			if args[0].compare(&Value::from(0)) == Some(Ordering::Less) {
+				args[0].neg(vm.heap)
+			} else {
+				Ok(args[0].clone())
+			}
+		} else {
+			Ok(Value::from(get_real(&args[0])?.abs()))
+		}
+	}));
+	let math_sqrt = heap.make_value(NativeFunction::new(|_vm, args| real_fn(args, f64::sqrt)));
+	let math_sin = heap.make_value(NativeFunction::new(|_vm, args| real_fn(args, f64::sin)));
+	let math_cos = heap.make_value(NativeFunction::new(|_vm, args| real_fn(args, f64::cos)));
+	let math_tan = heap.make_value(NativeFunction::new(|_vm, args| real_fn(args, f64::tan)));
+	let math_log = heap.make_value(NativeFunction::new(|_vm, args| real_fn(args, f64::ln)));
+	let math_exp = heap.make_value(NativeFunction::new(|_vm, args| real_fn(args, f64::exp)));
+	let math_min = heap.make_value(NativeFunction::new(|_vm, args| pick_by_order(args, Ordering::Less)));
+	let math_max = heap.make_value(NativeFunction::new(|_vm, args| pick_by_order(args, Ordering::Greater)));
+	let math_pi = Value::from(std::f64::consts::PI);
+	res.push(heap.make_value(
+		Namespace(vec![ math_floor, math_ceil, math_round, math_abs, math_sqrt, math_sin, math_cos, math_tan,
+			math_log, math_exp, math_min, math_max, math_pi ])
+	));
+	
+	let random_seed = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let seed = i64::try_from(&args[0]).map_err(|_| error(format!("Expected a 64-bit integer seed, got {}", args[0].repr())))?;
+		*vm.rng() = Rng::new(seed as u64);
+		Ok(NIL)
+	}));
+	let random_int = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 2)?;
+		let bounds = (i64::try_from(&args[0]), i64::try_from(&args[1]));
+		let (lo, hi) = if let (Ok(lo), Ok(hi)) = bounds { (lo, hi) }
+			else { return Err(error(format!("Expected 64-bit integer bounds, got {} and {}", args[0].repr(), args[1].repr()))); };
+		if lo > hi {
+			return Err(error(format!("Random range is empty: {} > {}", lo, hi)));
+		}
+		let i = vm.rng().int_in(lo, hi);
+		Ok(Value::from_i64(i, vm.heap))
+	}));
+	let random_real = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 0)?;
+		Ok(Value::from(vm.rng().next_real()))
+	}));
+	let random_choice = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let list = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let values = list.get_copy();
+		if values.is_empty() {
+			return Err(error(String::from("Cannot choose from an empty list")));
+		}
+		let idx = vm.rng().below(values.len() as u64) as usize;
+		Ok(values[idx].clone())
+	}));
+	let random_shuffle = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let list = GCRef::<List>::try_from(args[0].clone()).unwrap();
+		let mut values = list.get_copy();
+		for i in (1..values.len()).rev() { // Fisher-Yates
+			let j = vm.rng().below(i as u64 + 1) as usize;
+			values.swap(i, j);
+		}
+		list.replace(values);
+		Ok(NIL)
+	}));
+	res.push(heap.make_value(
+		Namespace(vec![ random_seed, random_int, random_real, random_choice, random_shuffle ])
+	));
+	
+	let fs_read_text = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let path = get_string(&args[0])?;
+		match fs_op(vm, &path, || fs::read_to_string(&path).map(Some), None)? {
+			Some(text) => vm.heap.try_make_value(text),
+			None => Ok(NIL),
+		}
+	}));
+	let fs_write_text = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 2)?;
+		let (path, text) = (get_string(&args[0])?, get_string(&args[1])?);
+		Ok(Value::from(fs_op(vm, &path, || fs::write(&path, &text).map(|_| true), false)?))
+	}));
+	let fs_append_text = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 2)?;
+		let (path, text) = (get_string(&args[0])?, get_string(&args[1])?);
+		let append = || OpenOptions::new().append(true).create(true).open(&path)?.write_all(text.as_bytes());
+		Ok(Value::from(fs_op(vm, &path, || append().map(|_| true), false)?))
+	}));
+	let fs_exists = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let path = get_string(&args[0])?;
+		// Unlike Path::exists, this tells a missing path from one which cannot be checked
+		let exists = || match fs::metadata(&path) {
+			Ok(_) => Ok(true),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+			Err(err) => Err(err),
+		};
+		Ok(Value::from(fs_op(vm, &path, exists, false)?))
+	}));
+	let fs_list_dir = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let path = get_string(&args[0])?;
+		let list = || fs::read_dir(&path)?
+			.map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+			.collect::<io::Result<Vec<String>>>();
+		let mut names = fs_op(vm, &path, list, vec![])?;
+		names.sort();
+		let list = vm.heap.make_ref(List::new());
+		for name in names {
+			let name = vm.heap.try_make_value(name)?;
+			push_checked(vm.heap, &list, name)?;
+		}
+		Ok(Value::from(list))
+	}));
+	let fs_remove = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let path = get_string(&args[0])?;
+		let remove = || if Path::new(&path).is_dir() { fs::remove_dir(&path) } else { fs::remove_file(&path) };
+		Ok(Value::from(fs_op(vm, &path, || remove().map(|_| true), false)?))
+	}));
+	let fs_mkdir = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let path = get_string(&args[0])?;
+		Ok(Value::from(fs_op(vm, &path, || fs::create_dir_all(&path).map(|_| true), false)?))
+	}));
+	let fs_last_error = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 0)?;
+		let msg = vm.fs_error()?.clone();
+		Ok(vm.heap.make_value(msg))
+	}));
+	res.push(heap.make_value(
+		Namespace(vec![ fs_read_text, fs_write_text, fs_append_text, fs_exists, fs_list_dir, fs_remove, fs_mkdir,
+			fs_last_error ])
+	));
+	
+	let json_parse = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let text = get_string(&args[0])?;
+		json::parse(&text, vm.heap)
+	}));
+	let json_stringify = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 2)?;
+		let indent = i64::try_from(&args[1]).ok().and_then(|n| usize::try_from(n).ok())
+			.ok_or_else(|| error(format!("Expected a non-negative indent, got {}", args[1].repr())))?;
+		let text = json::stringify(&args[0], indent, vm.heap)?;
+		vm.heap.try_make_value(text)
+	}));
+	res.push(heap.make_value(
+		Namespace(vec![ json_parse, json_stringify ])
+	));
+	
+	let time_now = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 0)?;
+		Ok(Value::from(vm.clock().now()))
+	}));
+	let time_wall = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 0)?;
+		Ok(Value::from(vm.clock().wall()))
+	}));
+	let time_sleep = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 1)?;
+		let secs = get_real(&args[0])?;
+		if !(secs >= 0.0 && secs.is_finite()) {
+			return Err(error(format!("Expected a non-negative number of seconds, got {}", args[0].repr())));
+		}
+		vm.clock().sleep(secs);
+		Ok(NIL)
+	}));
+	let time_format = heap.make_value(NativeFunction::new(|vm, args| {
+		check_arg_count(&args, 2)?;
+		let text = format_time(get_real(&args[0])?, &get_string(&args[1])?)?;
+		Ok(vm.heap.make_value(text))
+	}));
+	res.push(heap.make_value(
+		Namespace(vec![ time_now, time_wall, time_sleep, time_format ])
+	));
+	
+	(res, script_args)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::ErrorType;
+	use crate::compiler::Compiler;
+	use crate::vm::{VM, Outcome, gc::GCHeap, value::Value};
+	use super::super::tests::{eval, eval_with};
+	
+	const XS: &str = "let xs = [5, 3, 8]\n";
+	
+	#[test]
+	fn test_list_methods() {
+		let cases = [
+			("let p: Int = xs.pop()\nreturn [p, xs]\n", "[8, [5, 3]]"),
+			("xs.insert(0, 1)\nxs.insert(4, 9)\nreturn [xs.remove_at(1), xs.remove_at(3), xs]\n", "[5, 9, [1, 3, 8]]"),
+			("xs.insert(0 - 1, 4)\nreturn [xs.remove_at(0 - 2), xs.remove_at(0 - 3), xs]\n", "[4, 5, [3, 8]]"),
+			("return [xs.contains(8), xs.contains(2), xs.index_of(8), xs.index_of(2), [1.5, 2].contains(2.0)]\n",
+				"[true, false, 2, -1, true]"),
+			("xs.sort()\nlet s = xs.copy()\nxs.reverse()\nreturn [s, xs]\n", "[[3, 5, 8], [8, 5, 3]]"),
+			("let ys: List<Int> = xs.slice(1, 3)\nreturn [ys, xs.concat(ys), xs.size()]\n", "[[3, 8], [5, 3, 8, 3, 8], 3]"),
+			("xs.extend([0])\nlet n = xs.size()\nxs.clear()\nreturn [n, xs, xs.size()]\n", "[4, [], 0]"),
+			("let names = [\"b\", \"c\", \"a\"]\nnames.sort()\nreturn [names.join(\", \"), [1, 2.5, nil].join(\"-\")]\n",
+				"[\"a, b, c\", \"1-2.5-nil\"]"),
+		];
+		for (code, expected) in cases.iter() {
+			assert_eq!(eval(&[XS, code].concat()).unwrap(), *expected, "{}", code);
+		}
+		
+		let errors = [
+			("[].pop()\n", "Cannot pop from empty list"),
+			("xs.insert(5, 0)\n", "Index 5 is out of bounds for list of length 3"),
+			("xs.remove_at(3)\n", "Can't remove value at index 3 in list of length 3"),
+			("xs.remove_at(0 - 4)\n", "Index -4 is out of bounds for list of length 3"),
+			("xs.slice(2, 1)\n", "Slice start 2 is after its end 1"),
+			("[1, \"a\"].sort()\n", "Cannot compare \"a\" and 1"),
+		];
+		for (code, expected) in errors.iter() {
+			assert_eq!(eval(&[XS, code].concat()).unwrap_err().1, *expected, "{}", code);
+		}
+		assert!(matches!(eval(&[XS, "xs.add(\"a\")\n"].concat()).unwrap_err().0, ErrorType::Compilation));
+	}
+	
+	#[test]
+	fn test_conversions() {
+		assert_eq!(eval("return [int(3.7), int(-3.7), int(\" 42 \"), int(2 ^ 70), int(1e20)]\n").unwrap(),
+			"[3, -3, 42, 1180591620717411303424, 100000000000000000000]");
+		assert_eq!(eval("return [real(2), real(\"2.5\"), string(42), string([1, nil]), string(\"s\")]\n").unwrap(),
+			"[2.0, 2.5, \"42\", \"[1, nil]\", \"s\"]");
+		assert_eq!(eval("return [parse_int(\"12\"), parse_int(\"1.5\"), parse_real(\"1.5\"), parse_real(\"x\")]\n").unwrap(),
+			"[12, nil, 1.5, nil]");
+		
+		let errors = [
+			("return int(\"abc\")\n", "Cannot parse \"abc\" as an integer"),
+			("return int(nil)\n", "Cannot convert nil to an integer"),
+			("return int(1.0 / 0.0)\n", "Cannot convert inf to an integer"),
+			("return real(\"abc\")\n", "Cannot parse \"abc\" as a real"),
+			("let m: Map = map([1])\n", "Cannot convert [1] to a map"),
+			("let l: List<Any> = list(map(json.parse(\"{}\")))\n", "Cannot convert {} to a list"),
+		];
+		for (code, expected) in errors.iter() {
+			assert_eq!(eval(code).unwrap_err().1, *expected, "{}", code);
+		}
+	}
+	
+	#[test]
+	fn test_math() {
+		assert_eq!(eval("return [math.floor(-2.5), math.ceil(-2.5), math.round(2.5), math.round(7), math.floor(2 ^ 70)]\n").unwrap(),
+			"[-3, -2, 3, 7, 1180591620717411303424]");
+		assert_eq!(eval("return [math.abs(-3), math.abs(-2.5), math.abs(0 - 2 ^ 70), math.sqrt(16), math.exp(0)]\n").unwrap(),
+			"[3, 2.5, 1180591620717411303424, 4.0, 1.0]");
+		assert_eq!(eval("return [math.min(3, 2), math.max(3, 2), math.min(1.5, 2.5), math.max(\"a\", \"b\")]\n").unwrap(),
+			"[2, 3, 1.5, \"b\"]");
+		assert_eq!(eval("return [math.floor(math.pi * 100), math.round(math.sin(math.pi / 2)), math.log(1)]\n").unwrap(),
+			"[314, 1, 0.0]");
+		
+		assert_eq!(eval("return math.sqrt(\"x\")\n").unwrap_err().1, "Expected a number, got \"x\"");
+		assert_eq!(eval("return math.floor(0.0 / 0.0)\n").unwrap_err().1, "Cannot convert NaN to an integer");
+		assert_eq!(eval("let xs = [1, \"a\"]\nreturn math.min(xs[0], xs[1])\n").unwrap_err().1, "Cannot compare 1 and \"a\"");
+		// Both arguments of min and max must have the same type
+		assert!(matches!(eval("return math.min(1, \"a\")\n").unwrap_err().0, ErrorType::Compilation));
+	}
+	
+	#[test]
+	fn test_fs() {
+		let dir = std::env::temp_dir().join(format!("hissy_fs_{}", std::process::id()));
+		let file = dir.join("f.txt");
+		let code = format!("let dir = {0:?}
+let file = {1:?}
+let created = [fs.exists(dir), fs.mkdir({2:?}), fs.write_text(file, \"a\"), fs.append_text(file, \"b\")]
+let text = fs.read_text(file)
+let names = fs.list_dir(dir)
+let removed = [fs.remove(dir), fs.remove(file), fs.remove({2:?}), fs.remove(dir), fs.exists(dir)]
+let gone = fs.list_dir(dir)
+let missing = fs.read_text(file)
+let err = fs.last_error()
+fs.exists(dir)
+return [created, text, names, removed, gone, missing, err, fs.last_error()]
+", dir.to_str().unwrap(), file.to_str().unwrap(), dir.join("sub").to_str().unwrap());
+		let res = eval_with(&code, |vm, _| vm.allow_fs(true));
+		let _ = std::fs::remove_dir_all(&dir);
+		let res = res.unwrap();
+		assert!(res.starts_with("[[false, true, true, true], \"ab\", [\"f.txt\", \"sub\"], [false, true, true, true, false], [], nil, \""), "{}", res);
+		// Failures are described with their path, until the next operation succeeds
+		assert!(res.contains(&format!("\"{}: ", file.to_str().unwrap())), "{}", res);
+		assert!(res.ends_with("\", \"\"]"), "{}", res);
+		
+		// exists only returns false without an error if the path is missing
+		let file = std::env::temp_dir().join(format!("hissy_exists_{}.txt", std::process::id()));
+		let code = format!("fs.write_text({0:?}, \"a\")
+let res = [fs.exists({1:?}), fs.last_error(), fs.exists({0:?}), fs.last_error()]
+fs.remove({0:?})
+return res
+", file.to_str().unwrap(), file.join("x").to_str().unwrap());
+		let res = eval_with(&code, |vm, _| vm.allow_fs(true)).unwrap();
+		assert!(res.starts_with(&format!("[false, \"{}: ", file.join("x").to_str().unwrap())), "{}", res);
+		assert!(res.ends_with("\", true, \"\"]"), "{}", res);
+		
+		let err = eval("return fs.exists(\".\")\n").unwrap_err();
+		assert_eq!((err.1.as_str(), err.2), ("File system access is disabled", 1));
+	}
+	
+	#[test]
+	fn test_args_and_exit() {
+		let args = [String::from("a"), String::from("b c")];
+		assert_eq!(eval_with("return [args.size(), args[1]]\n", |vm, heap| vm.set_args(heap, &args)).unwrap(), "[2, \"b c\"]");
+		assert_eq!(eval("return args.size()\n").unwrap(), "0");
+		
+		let exits = [
+			("exit(3)\nreturn 1\n", 3),
+			("exit(0)\n", 0),
+			// Exits unwind through Hissy calls and natives calling back into Hissy
+			("let quit(x: Int) -> Int:\n\texit(x)\n\treturn x\n[1, 7].map(quit)\n", 1),
+			("let quit():\n\texit(5)\nlet co = coroutine(quit)\nco.resume()\n", 5),
+		];
+		for (code, status) in exits.iter() {
+			let program = Compiler::new(true).compile_program(code).unwrap();
+			let mut heap = GCHeap::new();
+			let mut vm = VM::new(&mut heap, &program);
+			assert!(matches!(vm.start_main(&mut heap).unwrap(), Outcome::Exited(n) if n == *status), "{}", code);
+			assert_eq!(vm.exit_code(), Some(*status));
+		}
+		assert_eq!(eval("exit(2 ^ 40)\n").unwrap_err().1, "Exit code 1099511627776 does not fit in 32 bits");
+		
+		// Where the host expects a value, an exit makes the run or call fail
+		assert_eq!(eval("exit(3)\n").unwrap_err().1, "Script exited with code 3");
+		let program = Compiler::new(true).compile_program("let quit(n: Int):\n\texit(n)\n").unwrap();
+		let mut heap = GCHeap::new();
+		let mut vm = VM::new(&mut heap, &program);
+		vm.run_main(&mut heap).unwrap();
+		assert_eq!(vm.exit_code(), None);
+		let quit = vm.get_global("quit").unwrap();
+		assert!(vm.call(&mut heap, &quit, &[Value::from(4)]).is_err());
+		assert_eq!(vm.exit_code(), Some(4));
+	}
 	
-	res
+	#[test]
+	fn test_env() {
+		std::env::set_var("HISSY_TEST_ENV", "set");
+		assert_eq!(eval("return [env(\"HISSY_TEST_ENV\"), env(\"HISSY_TEST_UNSET\")]\n").unwrap(), "[\"set\", nil]");
+	}
 }
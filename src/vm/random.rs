@@ -0,0 +1,106 @@
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Used to expand a single seed into the full generator state
+fn splitmix64(state: &mut u64) -> u64 {
+	*state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+	let mut z = *state;
+	z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	z ^ (z >> 31)
+}
+
+/// A deterministic pseudo-random number generator (xoshiro256**).
+///
+/// Two generators created with the same seed produce the same sequence of numbers.
+pub struct Rng {
+	state: [u64; 4],
+}
+
+impl Rng {
+	pub fn new(seed: u64) -> Rng {
+		let mut sm = seed;
+		let mut state = [0; 4];
+		for s in &mut state {
+			*s = splitmix64(&mut sm);
+		}
+		Rng { state }
+	}
+	
+	/// Creates a generator seeded with the current time, for runs which do not need to be reproducible.
+	pub fn from_time() -> Rng {
+		let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+		Rng::new(nanos as u64)
+	}
+	
+	pub fn next_u64(&mut self) -> u64 {
+		let s = &mut self.state;
+		let res = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+		let t = s[1] << 17;
+		s[2] ^= s[0];
+		s[3] ^= s[1];
+		s[1] ^= s[2];
+		s[0] ^= s[3];
+		s[2] ^= t;
+		s[3] = s[3].rotate_left(45);
+		res
+	}
+	
+	/// Returns a real uniformly distributed in [0, 1).
+	pub fn next_real(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+	
+	/// Returns an integer uniformly distributed in [0, bound), or any `u64` if `bound` is 0.
+	pub fn below(&mut self, bound: u64) -> u64 {
+		if bound == 0 {
+			return self.next_u64();
+		}
+		// Reject the lowest values, so that the remaining range is a multiple of bound
+		let threshold = bound.wrapping_neg() % bound;
+		loop {
+			let x = self.next_u64();
+			if x >= threshold {
+				return x % bound;
+			}
+		}
+	}
+	
+	/// Returns an integer uniformly distributed in [lo, hi]. `lo` must not be greater than `hi`.
+	pub fn int_in(&mut self, lo: i64, hi: i64) -> i64 {
+		let span = (i128::from(hi) - i128::from(lo) + 1) as u128;
+		let span = if span > u128::from(u64::MAX) { 0 } else { span as u64 };
+		(i128::from(lo) + i128::from(self.below(span))) as i64
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::Rng;
+	
+	#[test]
+	fn test_reproducible() {
+		let mut rng1 = Rng::new(42);
+		let mut rng2 = Rng::new(42);
+		let mut rng3 = Rng::new(43);
+		let seq1: Vec<u64> = (0..10).map(|_| rng1.next_u64()).collect();
+		let seq2: Vec<u64> = (0..10).map(|_| rng2.next_u64()).collect();
+		let seq3: Vec<u64> = (0..10).map(|_| rng3.next_u64()).collect();
+		assert_eq!(seq1, seq2);
+		assert_ne!(seq1, seq3);
+	}
+	
+	#[test]
+	fn test_bounds() {
+		let mut rng = Rng::new(0);
+		for _ in 0..1000 {
+			let i = rng.int_in(-3, 3);
+			assert!((-3..=3).contains(&i));
+			let r = rng.next_real();
+			assert!((0.0..1.0).contains(&r));
+		}
+		assert_eq!(rng.int_in(5, 5), 5);
+		rng.int_in(i64::MIN, i64::MAX);
+	}
+}
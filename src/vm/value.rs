@@ -4,7 +4,8 @@ use std::fmt;
 use num_enum::TryFromPrimitive;
 use std::convert::TryFrom;
 
-use super::gc::{GC, GCRef, GCWrapper};
+use super::gc::{GC, GCRef, GCWrapper, GCHeap};
+use super::object::BigInt;
 
 
 /// A Hissy value.
@@ -13,6 +14,7 @@ use super::gc::{GC, GCRef, GCWrapper};
 /// In the latter case, `Value` is the untyped equivalent of a [`GCRef`], and can be converted to/from one.
 /// 
 /// Internally, `Value`s are stored using NaN-tagging/boxing, so that non-object values are stored without heap allocation.
+/// Ints have arbitrary precision, but only those fitting in 48 bits are stored inline: larger ones are [`BigInt`]s in the GC heap.
 pub struct Value(Cell<u64>);
 
 #[derive(TryFromPrimitive, PartialEq)]
@@ -30,6 +32,8 @@ const TAG_SIZE: i8 = 16; // in bits
 const TAG_POS:  i8 = 64 - TAG_SIZE;
 const TAG_MIN:   u64 = 0xfff8 << TAG_POS;
 const DATA_MASK: u64 = std::u64::MAX >> TAG_SIZE;
+const INLINE_INT_MAX: i64 = (DATA_MASK >> 1) as i64;
+const INLINE_INT_MIN: i64 = -INLINE_INT_MAX - 1;
 
 const fn base_value(t: ValueType) -> u64 {
 	TAG_MIN + ((t as u64) << TAG_POS)
@@ -56,6 +60,39 @@ impl Value {
 		self.get_type() == ValueType::Nil
 	}
 	
+	/// Returns whether the `Value` contains an integer, inline or big.
+	pub fn is_int(&self) -> bool {
+		self.get_type() == ValueType::Int || matches!(self.get_pointer(), Some(p) if p.is_a::<BigInt>())
+	}
+	
+	fn inline_int(i: i64) -> Option<Value> {
+		if (INLINE_INT_MIN..=INLINE_INT_MAX).contains(&i) {
+			Some(Value::from_value(base_value(ValueType::Int) + (i as u64 & DATA_MASK)))
+		} else {
+			None
+		}
+	}
+	
+	/// Converts an `i64` into a `Value`, allocating a [`BigInt`] in the heap if it does not fit inline.
+	pub fn from_i64(i: i64, heap: &mut GCHeap) -> Value {
+		Value::inline_int(i).unwrap_or_else(|| heap.make_value(BigInt(num_bigint::BigInt::from(i))))
+	}
+	
+	/// Converts an arbitrary-precision integer into a `Value`, storing it inline if it fits.
+	pub fn from_bigint(b: num_bigint::BigInt, heap: &mut GCHeap) -> Value {
+		i64::try_from(&b).ok().and_then(Value::inline_int)
+			.unwrap_or_else(|| heap.make_value(BigInt(b)))
+	}
+	
+	/// Converts an integer `Value` into an arbitrary-precision integer. Returns `None` if it is not an integer.
+	pub fn to_bigint(&self) -> Option<num_bigint::BigInt> {
+		if self.get_type() == ValueType::Int {
+			i64::try_from(self).ok().map(num_bigint::BigInt::from)
+		} else {
+			self.get_pointer().and_then(|p| p.get::<BigInt>()).map(|b| b.0.clone())
+		}
+	}
+	
 	pub(super) fn from_pointer(pointer: *const GCWrapper, root: bool) -> Value {
 		let pointer = pointer as *mut () as u64; // Erases fat pointer data
 		assert!(pointer & DATA_MASK == pointer, "Object pointer has too many bits to fit in Value");
@@ -104,7 +141,7 @@ impl Value {
 	pub fn repr(&self) -> String {
 		match self.get_type() {
 			ValueType::Bool => bool::try_from(self).unwrap().to_string(),
-			ValueType::Int => i32::try_from(self).unwrap().to_string(),
+			ValueType::Int => i64::try_from(self).unwrap().to_string(),
 			ValueType::Real => {
 				let r = f64::try_from(self).unwrap();
 				if r.is_finite() {
@@ -176,7 +213,7 @@ impl Drop for Value {
 /// Converts an `i32` into a `Value` directly (no heap allocation is performed).
 impl From<i32> for Value {
 	fn from(i: i32) -> Self {
-		Value::from_value(base_value(ValueType::Int) + (i as u32 as u64))
+		Value::from_value(base_value(ValueType::Int) + (i64::from(i) as u64 & DATA_MASK))
 	}
 }
 
@@ -195,19 +232,29 @@ impl From<bool> for Value {
 	}
 }
 
-/// Attempts to convert a `Value` to an `i32`. Fails if the `Value` does not contain an integer.
-impl TryFrom<&Value> for i32 {
+/// Attempts to convert a `Value` to an `i64`. Fails if the `Value` does not contain an integer, or if it does not fit.
+impl TryFrom<&Value> for i64 {
 	type Error = &'static str;
 	fn try_from(value: &Value) -> std::result::Result<Self, &'static str> {
 		if value.get_type() == ValueType::Int {
-			assert!(value.0.get() & DATA_MASK <= std::u32::MAX as u64, "Invalid integer Value");
-			Ok((value.0.get() & DATA_MASK) as i32)
+			// Sign-extend the payload
+			Ok(((value.0.get() & DATA_MASK) << TAG_SIZE) as i64 >> TAG_SIZE)
+		} else if let Some(b) = value.get_pointer().and_then(|p| p.get::<BigInt>()) {
+			i64::try_from(&b.0).map_err(|_| "Integer value does not fit in 64 bits")
 		} else {
 			Err("Value is not an integer")
 		}
 	}
 }
 
+/// Attempts to convert a `Value` to an `i32`. Fails if the `Value` does not contain an integer, or if it does not fit.
+impl TryFrom<&Value> for i32 {
+	type Error = &'static str;
+	fn try_from(value: &Value) -> std::result::Result<Self, &'static str> {
+		i32::try_from(i64::try_from(value)?).map_err(|_| "Integer value does not fit in 32 bits")
+	}
+}
+
 /// Attempts to convert a `Value` to an `f64`. Fails if the Value does not contain a real.
 impl TryFrom<&Value> for f64 {
 	type Error = &'static str;
@@ -251,6 +298,32 @@ mod tests {
 		test_int(std::i32::MAX);
 		test_int(std::i32::MIN);
 	}
+	
+	#[test]
+	fn test_i64s() {
+		let mut heap = GCHeap::new();
+		for &i in &[0, -1, INLINE_INT_MAX, INLINE_INT_MIN, INLINE_INT_MAX + 1, INLINE_INT_MIN - 1, i64::MAX, i64::MIN] {
+			let val = Value::from_i64(i, &mut heap);
+			assert!(val.is_int());
+			assert_eq!(i64::try_from(&val), Ok(i));
+		}
+		assert_eq!(i32::try_from(&Value::from_i64(1 << 40, &mut heap)), Err("Integer value does not fit in 32 bits"));
+	}
+	
+	#[test]
+	fn test_bigints() {
+		let mut heap = GCHeap::new();
+		let big = num_bigint::BigInt::from(i64::MAX) * 1000u32;
+		let val = Value::from_bigint(big.clone(), &mut heap);
+		assert!(val.is_int());
+		assert_eq!(val.to_bigint(), Some(big));
+		assert_eq!(i64::try_from(&val), Err("Integer value does not fit in 64 bits"));
+		assert_eq!(val.repr(), "9223372036854775807000");
+		// Small results are demoted to inline integers
+		let val = Value::from_bigint(num_bigint::BigInt::from(-42), &mut heap);
+		assert!(val.get_pointer().is_none());
+		assert_eq!(i64::try_from(&val), Ok(-42));
+	}
 
 	fn test_real(d: f64) {
 		assert_eq!(f64::try_from(&Value::from(d)), Ok(d));